@@ -0,0 +1,413 @@
+//! Compares all `HashMapFamily` implementations over different key distributions and hashers,
+//! and prints the results as CSV with the columns `map,keys,hasher,size,metric,value`.
+//!
+//! Time metrics are in nanoseconds per operation (the median of a few runs), `bytes` is the
+//! heap memory held by a map after inserting all keys, and `capacity` the number of entries it
+//! has room for at that point. Pass a path in `HASHMAP_REPORT` to write the report to a file
+//! instead of stdout.
+//!
+//! ```text
+//! cargo bench --bench hashmap_report
+//! ```
+
+#![feature(allocator_api)]
+
+use std::{
+    alloc::{Allocator, Global, GlobalAlloc, Layout, System},
+    collections::{hash_map, TryReserveError},
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher, RandomState},
+    hint::black_box,
+    io::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use old_stuff::hashmaps::{
+    chained::ChainedHashMapFamily,
+    cuckoo::CuckooHashMapFamily,
+    hamt::HamtHashMapFamily,
+    indexed::IndexedHashMapFamily,
+    robin_hood::RobinHoodHashMapFamily,
+    simple_open_addressing::{SimpleOAHashMap, SimpleOAHashMapFamily},
+    small::SmallHashMapFamily,
+    swiss_table::SwissHashMapFamily,
+    HashMap, HashMapFamily,
+};
+
+/// The number of times every measurement is repeated.
+const RUNS: usize = 5;
+
+/// Keeps track of the number of bytes currently allocated.
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// `std`'s map, to have something to compare against. It doesn't support other allocators, so
+/// it only keeps one around for [`HashMap::allocator`].
+struct StdHashMap<K, V, S, A = Global>(hash_map::HashMap<K, V, S>, A);
+
+impl<K, V, S, A> IntoIterator for StdHashMap<K, V, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = hash_map::IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<K, V, S, A: Allocator> HashMap<K, V, S, A> for StdHashMap<K, V, S, A> {
+    type Iter<'a>
+        = hash_map::Iter<'a, K, V>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = hash_map::IterMut<'a, K, V>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self(hash_map::HashMap::with_hasher(state), alloc)
+    }
+
+    fn allocator(&self) -> &A {
+        &self.1
+    }
+
+    fn hasher(&self) -> &S {
+        self.0.hasher()
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.0.get(key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.0.get_mut(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.0.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.0.remove(key)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.0.try_reserve(additional)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.0.iter()
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        self.0.iter_mut()
+    }
+}
+
+struct StdHashMapFamily;
+impl HashMapFamily for StdHashMapFamily {
+    type Map<K, V, S, A: Allocator + Clone> = StdHashMap<K, V, S, A>;
+}
+
+/// The hasher of rustc, fast but not HashDoS resistant.
+#[derive(Default)]
+struct FxHasher(u64);
+
+impl Hasher for FxHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(u64::from(byte));
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = (self.0.rotate_left(5) ^ i).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// A key that always has the same hash, no matter the hasher.
+#[derive(Clone, PartialEq, Eq)]
+struct Colliding(u64);
+
+impl Hash for Colliding {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+/// A xorshift generator. It never repeats a number within its period of `2^64 - 1`,
+/// so all generated keys are distinct.
+struct XorShift(u64);
+
+impl Iterator for XorShift {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        Some(self.0)
+    }
+}
+
+struct Report {
+    out: Box<dyn Write>,
+}
+
+impl Report {
+    fn new() -> Self {
+        let out: Box<dyn Write> = match std::env::var_os("HASHMAP_REPORT") {
+            Some(path) => Box::new(std::fs::File::create(path).expect("creating report file")),
+            None => Box::new(std::io::stdout()),
+        };
+        let mut report = Self { out };
+        writeln!(report.out, "map,keys,hasher,size,metric,value").unwrap();
+        report
+    }
+
+    fn record(&mut self, run: &Run<'_>, metric: &str, value: impl std::fmt::Display) {
+        let Run {
+            map,
+            keys,
+            hasher,
+            size,
+        } = run;
+        writeln!(self.out, "{map},{keys},{hasher},{size},{metric},{value}").unwrap();
+    }
+}
+
+/// What is being measured.
+struct Run<'a> {
+    map: &'a str,
+    keys: &'a str,
+    hasher: &'a str,
+    size: usize,
+}
+
+/// Time `f` a few times, returning the median time per operation.
+/// `setup` runs before every measurement, outside of the timed section.
+fn measure<T, R>(ops: usize, mut setup: impl FnMut() -> T, mut f: impl FnMut(T) -> R) -> u128 {
+    let mut times = (0..RUNS)
+        .map(|_| {
+            let input = setup();
+            let start = Instant::now();
+            let output = black_box(f(input));
+            let elapsed = start.elapsed();
+            // Don't count dropping the output, which is often the map itself.
+            drop(output);
+            elapsed.as_nanos() / ops.max(1) as u128
+        })
+        .collect::<Vec<_>>();
+    times.sort_unstable();
+    times[RUNS / 2]
+}
+
+fn bench_map<M, K, H>(report: &mut Report, run: Run<'_>, present: &[K], missing: &[K], h: H)
+where
+    M: HashMapFamily,
+    K: Eq + Hash + Clone,
+    H: BuildHasher + Clone,
+{
+    let n = present.len();
+    let filled = || {
+        let mut m = M::Map::<K, usize, H, Global>::with_hasher(h.clone());
+        for (i, key) in present.iter().enumerate() {
+            m.insert(key.clone(), i);
+        }
+        m
+    };
+
+    let insert = measure(
+        n,
+        || present.to_vec(),
+        |keys| {
+            let mut m = M::Map::<_, _, _, Global>::with_hasher(h.clone());
+            for (i, key) in keys.into_iter().enumerate() {
+                m.insert(key, i);
+            }
+            m
+        },
+    );
+    report.record(&run, "insert_ns", insert);
+
+    let m = filled();
+    let get_hit = measure(
+        n,
+        || (),
+        |()| present.iter().filter(|key| m.get(key).is_some()).count(),
+    );
+    report.record(&run, "get_hit_ns", get_hit);
+    let get_miss = measure(
+        n,
+        || (),
+        |()| missing.iter().filter(|key| m.get(key).is_some()).count(),
+    );
+    report.record(&run, "get_miss_ns", get_miss);
+    drop(m);
+
+    let remove = measure(n, filled, |mut m| {
+        present.iter().filter(|key| m.remove(key).is_some()).count()
+    });
+    report.record(&run, "remove_ns", remove);
+
+    let iter = measure(n, filled, |m| m.into_iter().map(|(_, v)| v).sum::<usize>());
+    report.record(&run, "iter_ns", iter);
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let m = filled();
+    let bytes = ALLOCATED.load(Ordering::Relaxed).saturating_sub(before);
+    drop(m);
+    report.record(&run, "bytes", bytes);
+}
+
+/// Run all benchmarks for keys of one distribution, with every hasher.
+fn bench_keys<M, K>(report: &mut Report, map: &str, keys: &str, present: &[K], missing: &[K])
+where
+    M: HashMapFamily,
+    K: Eq + Hash + Clone,
+{
+    let run = |hasher| Run {
+        map,
+        keys,
+        hasher,
+        size: present.len(),
+    };
+    bench_map::<M, K, _>(report, run("sip"), present, missing, RandomState::new());
+    bench_map::<M, K, _>(
+        report,
+        run("fx"),
+        present,
+        missing,
+        BuildHasherDefault::<FxHasher>::default(),
+    );
+}
+
+fn bench_family<M: HashMapFamily>(report: &mut Report, map: &str) {
+    for size in [1_000, 100_000] {
+        let sequential = (0..2 * size as u64).collect::<Vec<_>>();
+        let (present, missing) = sequential.split_at(size);
+        bench_keys::<M, _>(report, map, "sequential_u64", present, missing);
+
+        let random = XorShift(0x2545_f491_4f6c_dd1d)
+            .take(2 * size)
+            .collect::<Vec<_>>();
+        let (present, missing) = random.split_at(size);
+        bench_keys::<M, _>(report, map, "random_u64", present, missing);
+
+        let strings = random.iter().map(|n| format!("{n:x}")).collect::<Vec<_>>();
+        let (present, missing) = strings.split_at(size);
+        bench_keys::<M, _>(report, map, "short_string", present, missing);
+    }
+
+    // Every operation is linear in the number of colliding keys, so keep this small.
+    let colliding = (0..2000).map(Colliding).collect::<Vec<_>>();
+    let (present, missing) = colliding.split_at(1000);
+    bench_keys::<M, _>(report, map, "colliding", present, missing);
+}
+
+/// The memory that the simple open addressing map trades for speed with a lower load factor
+/// or by reserving up front, to go along with the timings in `benches/hashmaps.rs`.
+fn bench_simple_oa_memory(report: &mut Report) {
+    type Map = SimpleOAHashMap<u64, usize>;
+    type NewMap = fn(usize) -> Map;
+    let configs: [(&str, NewMap); 4] = [
+        ("simple_oa_load_1/2", |_| {
+            let mut m = Map::new();
+            m.set_max_load_factor(1, 2);
+            m
+        }),
+        ("simple_oa_load_3/4", |_| {
+            let mut m = Map::new();
+            m.set_max_load_factor(3, 4);
+            m
+        }),
+        ("simple_oa_load_7/8", |_| Map::new()),
+        ("simple_oa_with_capacity", Map::with_capacity),
+    ];
+    for size in [1_000, 100_000] {
+        for (map, new) in configs {
+            let run = Run {
+                map,
+                keys: "sequential_u64",
+                hasher: "sip",
+                size,
+            };
+            let before = ALLOCATED.load(Ordering::Relaxed);
+            let mut m = new(size);
+            for i in 0..size {
+                m.insert(i as u64, i);
+            }
+            let bytes = ALLOCATED.load(Ordering::Relaxed).saturating_sub(before);
+            report.record(&run, "capacity", m.capacity());
+            report.record(&run, "bytes", bytes);
+        }
+    }
+}
+
+fn main() {
+    // `cargo test --benches` passes `--bench` only when benchmarking, don't waste time otherwise.
+    if !std::env::args().any(|arg| arg == "--bench") {
+        return;
+    }
+
+    let mut report = Report::new();
+    bench_family::<StdHashMapFamily>(&mut report, "std");
+    bench_family::<SimpleOAHashMapFamily>(&mut report, "simple_oa");
+    bench_family::<SwissHashMapFamily>(&mut report, "swiss");
+    bench_family::<RobinHoodHashMapFamily>(&mut report, "robin_hood");
+    bench_family::<ChainedHashMapFamily>(&mut report, "chained");
+    bench_family::<CuckooHashMapFamily>(&mut report, "cuckoo");
+    bench_family::<HamtHashMapFamily>(&mut report, "hamt");
+    bench_family::<IndexedHashMapFamily>(&mut report, "indexed");
+    bench_family::<SmallHashMapFamily<8>>(&mut report, "small_8");
+    bench_simple_oa_memory(&mut report);
+}
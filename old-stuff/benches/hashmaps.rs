@@ -0,0 +1,120 @@
+#![feature(test)]
+
+extern crate test;
+
+use old_stuff::hashmaps::{
+    chained::ChainedHashMap, cuckoo::CuckooHashMap, hamt::HamtHashMap, indexed::IndexedHashMap,
+    robin_hood::RobinHoodHashMap, simple_open_addressing::SimpleOAHashMap, small::SmallHashMap,
+    swiss_table::SwissHashMap, HashMap,
+};
+use test::{black_box, Bencher};
+
+const COUNT: usize = 10_000;
+
+/// The same benchmarks for every map. Our maps go through the `HashMap` trait,
+/// the `std` one uses its inherent methods of the same names.
+macro_rules! map_benches {
+    ($name:ident, $map:ty) => {
+        mod $name {
+            use super::*;
+
+            #[bench]
+            fn insert(b: &mut Bencher) {
+                b.iter(|| {
+                    let mut m = <$map>::new();
+                    for i in 0..COUNT {
+                        m.insert(i, i);
+                    }
+                    m
+                });
+            }
+
+            #[bench]
+            fn get(b: &mut Bencher) {
+                let mut m = <$map>::new();
+                for i in 0..COUNT {
+                    m.insert(i, i);
+                }
+                b.iter(|| {
+                    for i in 0..COUNT {
+                        black_box(m.get(&i));
+                    }
+                });
+            }
+
+            #[bench]
+            fn get_missing(b: &mut Bencher) {
+                let mut m = <$map>::new();
+                for i in 0..COUNT {
+                    m.insert(i, i);
+                }
+                b.iter(|| {
+                    for i in COUNT..(2 * COUNT) {
+                        black_box(m.get(&i));
+                    }
+                });
+            }
+        }
+    };
+}
+
+map_benches!(simple_oa, SimpleOAHashMap<usize, usize>);
+map_benches!(swiss, SwissHashMap<usize, usize>);
+map_benches!(robin_hood, RobinHoodHashMap<usize, usize>);
+map_benches!(chained, ChainedHashMap<usize, usize>);
+map_benches!(cuckoo, CuckooHashMap<usize, usize>);
+map_benches!(hamt, HamtHashMap<usize, usize>);
+map_benches!(indexed, IndexedHashMap<usize, usize>);
+map_benches!(std_hashmap, std::collections::HashMap<usize, usize>);
+
+/// The number of entries in the small maps, which fit inline in a `SmallHashMap<_, _, 8>`.
+const SMALL_COUNT: usize = 6;
+
+/// Building and querying many tiny maps, where allocating and hashing dominate.
+macro_rules! small_map_benches {
+    ($name:ident, $map:ty) => {
+        #[bench]
+        fn $name(b: &mut Bencher) {
+            b.iter(|| {
+                let mut m = <$map>::new();
+                for i in 0..SMALL_COUNT {
+                    m.insert(i, i);
+                }
+                for i in 0..2 * SMALL_COUNT {
+                    black_box(m.get(&i));
+                }
+                m
+            });
+        }
+    };
+}
+
+small_map_benches!(small_simple_oa, SimpleOAHashMap<usize, usize>);
+small_map_benches!(small_small_8, SmallHashMap<usize, usize, 8>);
+small_map_benches!(small_std_hashmap, std::collections::HashMap<usize, usize>);
+
+// These only measure time. `benches/hashmap_report.rs` reports the memory that reserving up front
+// and a lower load factor cost, in its `simple_oa_with_capacity` and `simple_oa_load_*` rows.
+
+#[bench]
+fn simple_oa_insert_with_capacity(b: &mut Bencher) {
+    b.iter(|| {
+        let mut m = SimpleOAHashMap::with_capacity(COUNT);
+        for i in 0..COUNT {
+            m.insert(i, i);
+        }
+        m
+    });
+}
+
+#[bench]
+fn simple_oa_insert_low_load(b: &mut Bencher) {
+    b.iter(|| {
+        let mut m = SimpleOAHashMap::new();
+        m.set_max_load_factor(1, 2);
+        for i in 0..COUNT {
+            m.insert(i, i);
+        }
+        m
+    });
+}
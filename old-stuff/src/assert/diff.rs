@@ -0,0 +1,318 @@
+//! Line diffs of `Debug` output, for values too big to compare by eye.
+//!
+//! Values are pretty-printed with `{:#?}` and compared line by line. Strings stay on one line
+//! when pretty-printed, so they are split after every `\n` escape instead. The lines are
+//! colored unless the `NO_COLOR` environment variable is set.
+
+use std::fmt::Debug;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Values whose `{:?}` output is longer than this are pretty-printed and diffed.
+const MAX_INLINE_LEN: usize = 60;
+
+/// The most cells of the LCS table, about 8 MB. If the lines between the common prefix and
+/// suffix need more, they are all shown as removed and added instead.
+const MAX_LCS_CELLS: usize = 1 << 20;
+
+/// Whether to color the output, see <https://no-color.org>.
+pub(super) fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// `value` formatted for a failure message after a label like `   found: `. Long values are
+/// pretty-printed and indented to line up with the label.
+pub(super) fn show(value: &dyn Debug) -> String {
+    let inline = format!("{value:?}");
+    if !is_long(&inline) {
+        return inline;
+    }
+    format!("{value:#?}").replace('\n', "\n          ")
+}
+
+/// Whether two values are big enough that a diff is easier to read than the values.
+pub(super) fn wants_diff(expected: &dyn Debug, found: &dyn Debug) -> bool {
+    [expected, found].iter().any(|value| {
+        let inline = format!("{value:?}");
+        is_long(&inline) || split_lines(&inline).len() > 1
+    })
+}
+
+fn is_long(inline: &str) -> bool {
+    inline.len() > MAX_INLINE_LEN || inline.contains('\n')
+}
+
+/// The lines of pretty-printed `Debug` output. A string is split after its `\n` escapes.
+fn split_lines(pretty: &str) -> Vec<&str> {
+    if pretty.contains('\n') {
+        return pretty.lines().collect();
+    }
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut chars = pretty.char_indices();
+    while let Some((_, c)) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+        // Skip the escaped character, so that an escaped backslash before an `n` doesn't count.
+        if let Some((i, 'n')) = chars.next() {
+            lines.push(&pretty[start..i + 1]);
+            start = i + 1;
+        }
+    }
+    lines.push(&pretty[start..]);
+    lines
+}
+
+#[derive(Clone, Copy)]
+enum Line<'a> {
+    Same(&'a str),
+    Expected(&'a str),
+    Found(&'a str),
+}
+
+/// An edit script turning `expected` into `found`. The lines that differ are diffed with
+/// [`lcs_diff`] if that doesn't take too much memory.
+fn diff_lines<'a>(expected: &[&'a str], found: &[&'a str]) -> Vec<Line<'a>> {
+    let prefix = expected
+        .iter()
+        .zip(found)
+        .take_while(|(expected, found)| expected == found)
+        .count();
+    let (expected_rest, found_rest) = (&expected[prefix..], &found[prefix..]);
+    let suffix = expected_rest
+        .iter()
+        .rev()
+        .zip(found_rest.iter().rev())
+        .take_while(|(expected, found)| expected == found)
+        .count();
+    let expected_middle = &expected_rest[..expected_rest.len() - suffix];
+    let found_middle = &found_rest[..found_rest.len() - suffix];
+
+    let mut lines = expected[..prefix]
+        .iter()
+        .map(|&line| Line::Same(line))
+        .collect::<Vec<_>>();
+    let cells = (expected_middle.len() + 1).saturating_mul(found_middle.len() + 1);
+    if cells <= MAX_LCS_CELLS {
+        lines.extend(lcs_diff(expected_middle, found_middle));
+    } else {
+        lines.extend(expected_middle.iter().map(|&line| Line::Expected(line)));
+        lines.extend(found_middle.iter().map(|&line| Line::Found(line)));
+    }
+    lines.extend(
+        expected_rest[expected_rest.len() - suffix..]
+            .iter()
+            .map(|&line| Line::Same(line)),
+    );
+    lines
+}
+
+/// The shortest edit script turning `expected` into `found`, from a longest common subsequence.
+fn lcs_diff<'a>(expected: &[&'a str], found: &[&'a str]) -> Vec<Line<'a>> {
+    let (n, m) = (expected.len(), found.len());
+    // `lcs[i][j]` is the length of the LCS of `expected[i..]` and `found[j..]`.
+    let mut lcs = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == found[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == found[j] {
+            lines.push(Line::Same(expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(Line::Expected(expected[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Found(found[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// A container the pretty-printer opened, like a struct, a tuple or a list.
+struct Frame<'a> {
+    indent: usize,
+    name: &'a str,
+    /// The number of elements seen so far, if this is a list.
+    elements: Option<usize>,
+}
+
+/// Where the first difference is, like `Config > servers[1] > Server > port`, found from the
+/// indentation of the pretty-printed lines up to the first difference.
+fn path_to_first_difference(lines: &[Line<'_>]) -> String {
+    let mut stack: Vec<Frame<'_>> = Vec::new();
+    for &line in lines {
+        let (Line::Same(text) | Line::Expected(text) | Line::Found(text)) = line;
+        let trimmed = text.trim();
+        let indent = text.len() - text.trim_start().len();
+        while stack.last().is_some_and(|frame| frame.indent >= indent) {
+            stack.pop();
+        }
+        if trimmed.starts_with(['}', ']', ')']) {
+            continue;
+        }
+        if let Some(Frame {
+            elements: Some(elements),
+            ..
+        }) = stack.last_mut()
+        {
+            *elements += 1;
+        }
+
+        let differs = !matches!(line, Line::Same(_));
+        if differs {
+            let mut path = stack
+                .iter()
+                .map(|frame| match frame.elements {
+                    Some(elements) => format!("{}[{}]", frame.name, elements - 1),
+                    None => frame.name.to_owned(),
+                })
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>();
+            let field = trimmed.split_once(": ").map(|(field, _)| field);
+            if let Some(field) = field.filter(|field| is_identifier(field)) {
+                path.push(field.to_owned());
+            }
+            return path.join(" > ");
+        }
+
+        if let Some(name) = trimmed.strip_suffix(['{', '[', '(']) {
+            stack.push(Frame {
+                indent,
+                name: name.trim_end().trim_end_matches(':'),
+                elements: trimmed.ends_with('[').then_some(0),
+            });
+        }
+    }
+    String::new()
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// A failure message showing how `found` differs from `expected`, line by line.
+pub(super) fn diff(expected: &dyn Debug, found: &dyn Debug, color: bool) -> String {
+    diff_text(&format!("{expected:#?}"), &format!("{found:#?}"), color)
+}
+
+/// Like [`diff`], for text that is already formatted.
+pub(super) fn diff_text(expected: &str, found: &str, color: bool) -> String {
+    let lines = diff_lines(&split_lines(expected), &split_lines(found));
+
+    let mut message = "values differ (- expected, + found)".to_owned();
+    let path = path_to_first_difference(&lines);
+    if !path.is_empty() {
+        message += &format!(", first at `{path}`");
+    }
+    message.push(':');
+    for line in lines {
+        let (sign, text, start) = match line {
+            Line::Same(text) => (' ', text, ""),
+            Line::Expected(text) => ('-', text, RED),
+            Line::Found(text) => ('+', text, GREEN),
+        };
+        message.push('\n');
+        if color && !start.is_empty() {
+            message += &format!("{start}{sign} {text}{RESET}");
+        } else {
+            message += &format!("{sign} {text}");
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Server {
+        host: &'static str,
+        port: u16,
+    }
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        name: &'static str,
+        servers: Vec<Server>,
+    }
+
+    #[test]
+    fn first_differing_element() {
+        let config = |port| Config {
+            name: "test",
+            servers: vec![
+                Server {
+                    host: "a",
+                    port: 80,
+                },
+                Server { host: "b", port },
+            ],
+        };
+        let message = diff(&config(80), &config(8080), false);
+        let expected = "\
+values differ (- expected, + found), first at `Config > servers[1] > Server > port`:
+  Config {
+      name: \"test\",
+      servers: [
+          Server {
+              host: \"a\",
+              port: 80,
+          },
+          Server {
+              host: \"b\",
+-             port: 80,
++             port: 8080,
+          },
+      ],
+  }";
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn multi_line_strings() {
+        let message = diff(&"one\ntwo\nthree", &"one\n2\nthree\\n", true);
+        let expected = format!(
+            "values differ (- expected, + found):
+  \"one\\n
+{RED}- two\\n{RESET}
+{RED}- three\"{RESET}
+{GREEN}+ 2\\n{RESET}
+{GREEN}+ three\\\\n\"{RESET}"
+        );
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn big_diffs_without_lcs() {
+        let expected = (0..2000).map(|i| i.to_string()).collect::<Vec<_>>();
+        let mut found = (0..2000).map(|i| (i * 7).to_string()).collect::<Vec<_>>();
+        found[1999] = "1999".to_owned();
+        let expected = expected.iter().map(String::as_str).collect::<Vec<_>>();
+        let found = found.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let lines = diff_lines(&expected, &found);
+        assert_eq!(lines.len(), 1 + 1998 * 2 + 1);
+        assert!(matches!(lines[0], Line::Same("0")));
+        assert!(matches!(lines[1], Line::Expected("1")));
+        assert!(matches!(lines[1999], Line::Found("7")));
+        assert!(matches!(lines[3997], Line::Same("1999")));
+    }
+}
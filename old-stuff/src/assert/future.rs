@@ -0,0 +1,215 @@
+//! Assertions on futures, polling them by hand instead of running them on an executor.
+//!
+//! This makes it possible to check the states of a future in between polls, like that it is
+//! still pending before some other thread made progress.
+
+use super::{diff, Assert};
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+/// Counts how often it was woken, and unparks the thread that created it.
+struct CountingWaker {
+    wakes: AtomicUsize,
+    thread: Thread,
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+/// A future under test, see [`assert_future`].
+pub struct PolledFuture<F> {
+    fut: Pin<Box<F>>,
+    waker: Arc<CountingWaker>,
+    polls: usize,
+}
+
+impl<F: Future> PolledFuture<F> {
+    fn poll(&mut self) -> Poll<F::Output> {
+        self.polls += 1;
+        let waker = Waker::from(self.waker.clone());
+        self.fut.as_mut().poll(&mut Context::from_waker(&waker))
+    }
+
+    fn wakes(&self) -> usize {
+        self.waker.wakes.load(Ordering::SeqCst)
+    }
+}
+
+impl<F> Debug for PolledFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolledFuture")
+            .field("polls", &self.polls)
+            .field("wakes", &self.waker.wakes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Start checking a future. It is polled on the current thread, with a waker that counts how
+/// often it was woken.
+///
+/// ```
+/// use old_stuff::assert::assert_future;
+///
+/// assert_future(async { 1 + 1 }).completes_within(std::time::Duration::from_secs(1)).equals(2);
+/// ```
+pub fn assert_future<F: Future>(fut: F) -> Assert<PolledFuture<F>> {
+    let waker = CountingWaker {
+        wakes: AtomicUsize::new(0),
+        thread: thread::current(),
+    };
+    Assert {
+        v: PolledFuture {
+            fut: Box::pin(fut),
+            waker: Arc::new(waker),
+            polls: 0,
+        },
+        soft: None,
+    }
+}
+
+impl<F: Future> Assert<PolledFuture<F>>
+where
+    F::Output: Debug,
+{
+    /// Poll the future once, and check that it is still pending.
+    #[track_caller]
+    pub fn is_pending_after_poll(mut self) -> Self {
+        if let Poll::Ready(output) = self.v.poll() {
+            let found = diff::show(&output);
+            self.fail(format!(
+                "expected: a pending future\n   found: ready with {found}"
+            ));
+        }
+        self
+    }
+
+    /// Poll the future until it completes, and return its output for further checks. The
+    /// future is only polled again after it woke its waker, like on an executor, so a future
+    /// that forgets to wake it doesn't complete.
+    #[track_caller]
+    pub fn completes_within(mut self, timeout: Duration) -> Assert<F::Output> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Read the count before polling, so that wakes during the poll aren't missed.
+            let wakes = self.v.wakes();
+            if let Poll::Ready(output) = self.v.poll() {
+                return self.map(|_| output);
+            }
+            while self.v.wakes() == wakes {
+                let now = Instant::now();
+                if now >= deadline {
+                    // There is no output to check further, so this can't be a soft failure.
+                    let found = &self.v;
+                    panic!("expected: a future completing within {timeout:?}\n   found: {found:?}");
+                }
+                thread::park_timeout(deadline - now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert::{assert, tests::panic_message};
+    use std::{
+        future,
+        sync::{mpsc, Mutex},
+    };
+
+    /// A future that returns `Pending` once, waking itself.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = &'static str;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.0 {
+                return Poll::Ready("done");
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    /// The result of a computation on another thread, which wakes the waker when it's done.
+    #[derive(Default)]
+    struct Shared {
+        result: Option<u32>,
+        waker: Option<Waker>,
+    }
+
+    struct Handle(Arc<Mutex<Shared>>);
+
+    impl Future for Handle {
+        type Output = u32;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            let mut shared = self.0.lock().unwrap();
+            match shared.result.take() {
+                Some(result) => Poll::Ready(result),
+                None => {
+                    shared.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn futures() {
+        let second = Duration::from_secs(1);
+        assert_future(YieldOnce(false))
+            .is_pending_after_poll()
+            .completes_within(second)
+            .equals("done");
+
+        let message = panic_message(|| {
+            assert_future(YieldOnce(true)).is_pending_after_poll();
+        });
+        assert(message).equals("expected: a pending future\n   found: ready with \"done\"");
+        let message = panic_message(|| {
+            assert_future(future::pending::<()>()).completes_within(Duration::from_millis(10));
+        });
+        assert(message).starts_with("expected: a future completing within 10ms");
+    }
+
+    #[test]
+    fn woken_from_another_thread() {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let (start, started) = mpsc::channel();
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                started.recv().unwrap();
+                let mut shared = shared.lock().unwrap();
+                shared.result = Some(42);
+                shared.waker.take().unwrap().wake();
+            })
+        };
+
+        let handle = assert_future(Handle(shared)).is_pending_after_poll();
+        start.send(()).unwrap();
+        handle.completes_within(Duration::from_secs(5)).equals(42);
+        thread.join().unwrap();
+    }
+}
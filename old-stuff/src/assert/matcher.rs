@@ -0,0 +1,266 @@
+//! Matchers that can be defined outside of this module, and composed into bigger ones.
+//!
+//! A [`Matcher`] describes what a matching value looks like, so that the descriptions compose
+//! into failure messages like "expected: a Vec containing an item with len > 3".
+
+use super::Assert;
+use std::{any, fmt::Debug};
+
+/// A check on values of type `T`, used with [`Assert::satisfies`].
+pub trait Matcher<T: ?Sized> {
+    fn matches(&self, value: &T) -> bool;
+
+    /// What matching values look like, to complete "expected: ...". It may be used inside the
+    /// descriptions of other matchers, so it shouldn't end with punctuation.
+    fn describe(&self) -> String;
+}
+
+impl<T: Debug> Assert<T> {
+    #[track_caller]
+    pub fn satisfies(self, matcher: impl Matcher<T>) {
+        self.check(matcher.matches(&self.v), || matcher.describe(), &self.v);
+    }
+}
+
+/// A matcher from a description and a predicate.
+pub fn predicate<T: ?Sized>(
+    description: impl Into<String>,
+    f: impl Fn(&T) -> bool,
+) -> impl Matcher<T> {
+    struct Predicate<F>(String, F);
+    impl<T: ?Sized, F: Fn(&T) -> bool> Matcher<T> for Predicate<F> {
+        fn matches(&self, value: &T) -> bool {
+            (self.1)(value)
+        }
+        fn describe(&self) -> String {
+            self.0.clone()
+        }
+    }
+    Predicate(description.into(), f)
+}
+
+/// Matchers comparing values with a fixed one.
+macro_rules! comparison_matchers {
+    ($($(#[$attr:meta])* $name:ident $op:tt $bound:ident;)*) => {$(
+        $(#[$attr])*
+        pub fn $name<T: $bound<U> + ?Sized, U: Debug>(other: U) -> impl Matcher<T> {
+            predicate(
+                format!("{} {other:?}", stringify!($op)),
+                move |value: &T| *value $op other,
+            )
+        }
+    )*};
+}
+
+comparison_matchers! {
+    eq == PartialEq;
+    ne != PartialEq;
+    gt > PartialOrd;
+    ge >= PartialOrd;
+    lt < PartialOrd;
+    le <= PartialOrd;
+}
+
+pub struct Not<M>(M);
+
+/// Matches values that `matcher` doesn't match.
+pub fn not<M>(matcher: M) -> Not<M> {
+    Not(matcher)
+}
+
+impl<T: ?Sized, M: Matcher<T>> Matcher<T> for Not<M> {
+    fn matches(&self, value: &T) -> bool {
+        !self.0.matches(value)
+    }
+
+    fn describe(&self) -> String {
+        format!("not {}", self.0.describe())
+    }
+}
+
+/// A tuple of matchers for the same type, for [`all_of`] and [`any_of`].
+pub trait MatcherTuple<T: ?Sized> {
+    fn all(&self, value: &T) -> bool;
+
+    fn any(&self, value: &T) -> bool;
+
+    fn describe_each(&self) -> Vec<String>;
+}
+
+macro_rules! impl_matcher_tuple {
+    ($(($($m:ident $i:tt),+))*) => {$(
+        impl<T: ?Sized, $($m: Matcher<T>),+> MatcherTuple<T> for ($($m,)+) {
+            fn all(&self, value: &T) -> bool {
+                $(self.$i.matches(value))&&+
+            }
+
+            fn any(&self, value: &T) -> bool {
+                $(self.$i.matches(value))||+
+            }
+
+            fn describe_each(&self) -> Vec<String> {
+                vec![$(self.$i.describe()),+]
+            }
+        }
+    )*};
+}
+
+impl_matcher_tuple! {
+    (A 0)
+    (A 0, B 1)
+    (A 0, B 1, C 2)
+    (A 0, B 1, C 2, D 3)
+    (A 0, B 1, C 2, D 3, E 4)
+    (A 0, B 1, C 2, D 3, E 4, F 5)
+}
+
+pub struct AllOf<M>(M);
+
+/// Matches values that all of the tuple of `matchers` match.
+pub fn all_of<M>(matchers: M) -> AllOf<M> {
+    AllOf(matchers)
+}
+
+impl<T: ?Sized, M: MatcherTuple<T>> Matcher<T> for AllOf<M> {
+    fn matches(&self, value: &T) -> bool {
+        self.0.all(value)
+    }
+
+    fn describe(&self) -> String {
+        format!("({})", self.0.describe_each().join(" and "))
+    }
+}
+
+pub struct AnyOf<M>(M);
+
+/// Matches values that any of the tuple of `matchers` matches.
+pub fn any_of<M>(matchers: M) -> AnyOf<M> {
+    AnyOf(matchers)
+}
+
+impl<T: ?Sized, M: MatcherTuple<T>> Matcher<T> for AnyOf<M> {
+    fn matches(&self, value: &T) -> bool {
+        self.0.any(value)
+    }
+
+    fn describe(&self) -> String {
+        format!("({})", self.0.describe_each().join(" or "))
+    }
+}
+
+/// The name of a type without its path and generic arguments, like `Vec` for `&Vec<String>`.
+/// Arrays and slices are called `array` and `slice`.
+fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = any::type_name::<T>().trim_start_matches('&');
+    if name.starts_with('[') {
+        // Only arrays end in `; N]`, the element type of a slice is in brackets.
+        let is_array = name
+            .strip_suffix(']')
+            .and_then(|name| name.rsplit_once("; "))
+            .is_some_and(|(_, len)| len.bytes().all(|b| b.is_ascii_digit()));
+        return if is_array { "array" } else { "slice" };
+    }
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+pub struct ContainsItem<M>(M);
+
+/// Matches collections with at least one item matching `matcher`.
+pub fn contains_item<M>(matcher: M) -> ContainsItem<M> {
+    ContainsItem(matcher)
+}
+
+impl<C: ?Sized, I, M: Matcher<I>> Matcher<C> for ContainsItem<M>
+where
+    for<'a> &'a C: IntoIterator<Item = &'a I>,
+{
+    fn matches(&self, value: &C) -> bool {
+        value.into_iter().any(|item| self.0.matches(item))
+    }
+
+    fn describe(&self) -> String {
+        let collection = short_type_name::<C>();
+        let article = if collection.starts_with(['a', 'e', 'i', 'o', 'u', 'A', 'E', 'I', 'O', 'U'])
+        {
+            "an"
+        } else {
+            "a"
+        };
+        format!(
+            "{article} {collection} containing an item {}",
+            self.0.describe()
+        )
+    }
+}
+
+pub struct HasField<G, M> {
+    name: &'static str,
+    get: G,
+    matcher: M,
+}
+
+/// Matches values where `matcher` matches the part of the value returned by `get`, which is
+/// called `name` in the description.
+pub fn has_field<G, M>(name: &'static str, get: G, matcher: M) -> HasField<G, M> {
+    HasField { name, get, matcher }
+}
+
+impl<T: ?Sized, F, G: Fn(&T) -> F, M: Matcher<F>> Matcher<T> for HasField<G, M> {
+    fn matches(&self, value: &T) -> bool {
+        self.matcher.matches(&(self.get)(value))
+    }
+
+    fn describe(&self) -> String {
+        format!("with {} {}", self.name, self.matcher.describe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert::{assert, tests::panic_message};
+
+    struct IsEven;
+    impl Matcher<i32> for IsEven {
+        fn matches(&self, value: &i32) -> bool {
+            value % 2 == 0
+        }
+        fn describe(&self) -> String {
+            "even".to_owned()
+        }
+    }
+
+    #[test]
+    fn custom_matcher() {
+        assert(4).satisfies(IsEven);
+        assert(3).satisfies(not(IsEven));
+        assert(4).satisfies(all_of((IsEven, gt(2), le(4))));
+        assert(5).satisfies(any_of((IsEven, eq(5))));
+        assert("uwu").satisfies(predicate("a palindrome", |s: &&str| {
+            s.chars().eq(s.chars().rev())
+        }));
+
+        let message = panic_message(|| assert(3).satisfies(all_of((IsEven, not(eq(3))))));
+        assert(message).equals("expected: (even and not == 3)\n   found: 3");
+    }
+
+    #[test]
+    fn nested_matchers() {
+        let words = vec!["a".to_owned(), "long".to_owned()];
+        let len = |s: &String| s.len();
+        assert(words.clone()).satisfies(contains_item(has_field("len", len, gt(3))));
+        assert([1, 2, 3]).satisfies(contains_item(ge(3)));
+
+        let message = panic_message(|| {
+            assert(words).satisfies(contains_item(has_field("len", len, gt(4))));
+        });
+        let expected =
+            "expected: a Vec containing an item with len > 4\n   found: [\"a\", \"long\"]";
+        assert(message).equals(expected);
+
+        let message = panic_message(|| assert([1, 2, 3]).satisfies(contains_item(gt(5))));
+        assert(message).equals("expected: an array containing an item > 5\n   found: [1, 2, 3]");
+        assert(short_type_name::<&[[i32; 2]]>()).equals("slice");
+    }
+}
@@ -0,0 +1,496 @@
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    panic::{self, AssertUnwindSafe, Location},
+    rc::Rc,
+};
+
+mod diff;
+mod future;
+mod matcher;
+mod panics;
+mod snapshot;
+
+pub use future::{assert_future, PolledFuture};
+pub use matcher::{
+    all_of, any_of, contains_item, eq, ge, gt, has_field, le, lt, ne, not, predicate, AllOf, AnyOf,
+    ContainsItem, HasField, Matcher, MatcherTuple, Not,
+};
+pub use panics::{assert_panics, Panic};
+
+pub fn assert<T>(v: T) -> Assert<T> {
+    Assert { v, soft: None }
+}
+
+/// Run `f` with soft assertions: checks made through [`Soft::that`] don't stop the test when
+/// they fail, but are all reported together once `f` returns. If `f` panics, the failures so
+/// far are printed before the panic continues.
+///
+/// ```should_panic
+/// use old_stuff::assert::soft;
+///
+/// soft(|s| {
+///     s.that(1 + 1).equals(3);
+///     s.that("uwu").starts_with("owo");
+/// });
+/// ```
+#[track_caller]
+pub fn soft(f: impl FnOnce(&Soft)) {
+    let soft = Soft::default();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&soft)));
+    let failures = soft.failures.take();
+    let report = (!failures.is_empty()).then(|| {
+        let mut report = format!("{} soft assertion(s) failed:", failures.len());
+        for Failure { location, message } in failures {
+            report += &format!("\n\n{location}:\n{message}");
+        }
+        report
+    });
+    match (result, report) {
+        (Ok(()), None) => {}
+        (Ok(()), Some(report)) => panic!("{report}"),
+        (Err(payload), report) => {
+            if let Some(report) = report {
+                eprintln!("{report}");
+            }
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+/// Collects the failures of soft assertions, see [`soft`].
+#[derive(Default)]
+pub struct Soft {
+    failures: Rc<RefCell<Vec<Failure>>>,
+}
+
+struct Failure {
+    location: &'static Location<'static>,
+    message: String,
+}
+
+impl Soft {
+    /// Start a soft assertion on `v`. All matchers of [`Assert`] can be used.
+    pub fn that<T>(&self, v: T) -> Assert<T> {
+        Assert {
+            v,
+            soft: Some(self.failures.clone()),
+        }
+    }
+}
+
+pub struct Assert<T> {
+    v: T,
+    /// Where failures are recorded instead of panicking, in soft mode.
+    soft: Option<Rc<RefCell<Vec<Failure>>>>,
+}
+
+impl<T> Assert<T> {
+    /// Check a value derived from the one under test.
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Assert<U> {
+        Assert {
+            v: f(self.v),
+            soft: self.soft,
+        }
+    }
+
+    /// Fail with a message saying what was `expected` and what was `found` instead, unless `ok`.
+    #[track_caller]
+    fn check(&self, ok: bool, expected: impl FnOnce() -> String, found: &dyn Debug) {
+        if !ok {
+            self.fail(format!(
+                "expected: {}\n   found: {}",
+                expected(),
+                diff::show(found)
+            ));
+        }
+    }
+
+    /// Like [`Assert::check`], but if the values are too big to compare at a glance, the
+    /// message is a diff of `diff_expected` and `diff_found` instead. Matchers that don't check
+    /// for equality arrange these so that only the offending items differ.
+    #[track_caller]
+    fn check_diff(
+        &self,
+        ok: bool,
+        expected: impl FnOnce() -> String,
+        found: &dyn Debug,
+        (diff_expected, diff_found): (&dyn Debug, &dyn Debug),
+    ) {
+        if ok {
+            return;
+        }
+        if diff::wants_diff(diff_expected, diff_found) {
+            self.fail(diff::diff(diff_expected, diff_found, diff::use_color()));
+        } else {
+            self.check(ok, expected, found);
+        }
+    }
+
+    /// Panic with `message`, or record it in soft mode.
+    #[track_caller]
+    fn fail(&self, message: String) {
+        match &self.soft {
+            Some(failures) => failures.borrow_mut().push(Failure {
+                location: Location::caller(),
+                message,
+            }),
+            None => panic!("{message}"),
+        }
+    }
+}
+
+impl Assert<bool> {
+    #[track_caller]
+    pub fn is_true(self) {
+        self.check(self.v, || "true".to_owned(), &self.v);
+    }
+    #[track_caller]
+    pub fn is_false(self) {
+        self.check(!self.v, || "false".to_owned(), &self.v);
+    }
+}
+
+impl<T: Debug> Assert<T> {
+    #[track_caller]
+    pub fn equals<U: Debug>(self, other: U)
+    where
+        T: PartialEq<U>,
+    {
+        let expected = || format!("{other:?}");
+        self.check_diff(self.v == other, expected, &self.v, (&other, &self.v));
+    }
+    #[track_caller]
+    pub fn not_equals<U: Debug>(self, other: U)
+    where
+        T: PartialEq<U>,
+    {
+        let expected = || format!("anything but {}", diff::show(&other));
+        self.check(self.v != other, expected, &self.v);
+    }
+
+    #[track_caller]
+    pub fn is_greater_than<U: Debug>(self, other: U)
+    where
+        T: PartialOrd<U>,
+    {
+        let expected = || format!("a value greater than {other:?}");
+        self.check(self.v > other, expected, &self.v);
+    }
+    #[track_caller]
+    pub fn is_less_than<U: Debug>(self, other: U)
+    where
+        T: PartialOrd<U>,
+    {
+        let expected = || format!("a value less than {other:?}");
+        self.check(self.v < other, expected, &self.v);
+    }
+    /// Both bounds are inclusive.
+    #[track_caller]
+    pub fn is_between<U: Debug>(self, low: U, high: U)
+    where
+        T: PartialOrd<U>,
+    {
+        let expected = || format!("a value between {low:?} and {high:?}");
+        self.check(self.v >= low && self.v <= high, expected, &self.v);
+    }
+}
+
+impl<T: Debug> Assert<Option<T>> {
+    #[track_caller]
+    pub fn is_some(self) {
+        self.check(self.v.is_some(), || "Some(_)".to_owned(), &self.v);
+    }
+    #[track_caller]
+    pub fn is_none(self) {
+        self.check(self.v.is_none(), || "None".to_owned(), &self.v);
+    }
+    #[track_caller]
+    pub fn is_some_and(self, f: impl FnOnce(&T) -> bool) {
+        let ok = self.v.as_ref().is_some_and(f);
+        self.check(ok, || "Some(_) matching the predicate".to_owned(), &self.v);
+    }
+}
+
+impl<T: Debug, E: Debug> Assert<Result<T, E>> {
+    #[track_caller]
+    pub fn is_ok(self) {
+        self.check(self.v.is_ok(), || "Ok(_)".to_owned(), &self.v);
+    }
+    #[track_caller]
+    pub fn is_err(self) {
+        self.check(self.v.is_err(), || "Err(_)".to_owned(), &self.v);
+    }
+    #[track_caller]
+    pub fn is_ok_and(self, f: impl FnOnce(&T) -> bool) {
+        let ok = self.v.as_ref().is_ok_and(f);
+        self.check(ok, || "Ok(_) matching the predicate".to_owned(), &self.v);
+    }
+    #[track_caller]
+    pub fn is_err_and(self, f: impl FnOnce(&E) -> bool) {
+        let ok = self.v.as_ref().is_err_and(f);
+        self.check(ok, || "Err(_) matching the predicate".to_owned(), &self.v);
+    }
+}
+
+/// Matchers for anything that can be iterated over, like `Vec`s, arrays, sets or maps, or
+/// references to them.
+impl<T: IntoIterator> Assert<T>
+where
+    T::Item: Debug,
+{
+    #[track_caller]
+    pub fn has_len(self, len: usize) {
+        let items = self.map(|v| v.into_iter().collect::<Vec<_>>());
+        let expected = || format!("a collection of length {len}");
+        items.check(items.v.len() == len, expected, &items.v);
+    }
+    #[track_caller]
+    pub fn is_empty(self) {
+        self.has_len(0);
+    }
+    #[track_caller]
+    pub fn contains_item<U: Debug>(self, item: U)
+    where
+        T::Item: PartialEq<U>,
+    {
+        let items = self.map(|v| v.into_iter().collect::<Vec<_>>());
+        let expected = || format!("a collection containing {item:?}");
+        let ok = items.v.iter().any(|elem| *elem == item);
+        items.check(ok, expected, &items.v);
+    }
+    /// The collection contains the same items as `expected`, each the same number of times,
+    /// but in any order.
+    #[track_caller]
+    pub fn contains_exactly_in_any_order<U: Debug>(self, expected: impl IntoIterator<Item = U>)
+    where
+        T::Item: PartialEq<U>,
+    {
+        let items = self.map(|v| v.into_iter().collect::<Vec<_>>());
+        let expected = expected.into_iter().collect::<Vec<_>>();
+        let mut matched = vec![false; items.v.len()];
+        let matches = expected
+            .iter()
+            .map(|wanted| {
+                let found = (0..items.v.len()).find(|&i| !matched[i] && items.v[i] == *wanted);
+                found.inspect(|&i| matched[i] = true)
+            })
+            .collect::<Vec<_>>();
+        let ok = matches.iter().all(Option::is_some) && items.v.len() == expected.len();
+        if ok {
+            return;
+        }
+        // For the diff, the found items are put in the order of the expected ones they
+        // matched, followed by the ones that matched nothing.
+        let unmatched = (0..items.v.len()).filter(|&i| !matched[i]);
+        let reordered = matches
+            .iter()
+            .flatten()
+            .copied()
+            .chain(unmatched)
+            .map(|i| &items.v[i])
+            .collect::<Vec<_>>();
+        let describe = || format!("exactly {expected:?} in any order");
+        items.check_diff(ok, describe, &items.v, (&expected, &reordered));
+    }
+    #[track_caller]
+    pub fn is_sorted(self)
+    where
+        T::Item: PartialOrd,
+    {
+        let items = self.map(|v| v.into_iter().collect::<Vec<_>>());
+        let ok = items.v.is_sorted();
+        items.check(ok, || "a sorted collection".to_owned(), &items.v);
+    }
+}
+
+impl<T: AsRef<str>> Assert<T> {
+    #[track_caller]
+    pub fn contains(self, other: &str) {
+        let s = self.v.as_ref();
+        let expected = || format!("a string containing {other:?}");
+        self.check(s.contains(other), expected, &s);
+    }
+    #[track_caller]
+    pub fn starts_with(self, prefix: &str) {
+        let s = self.v.as_ref();
+        let expected = || format!("a string starting with {prefix:?}");
+        self.check(s.starts_with(prefix), expected, &s);
+    }
+    #[track_caller]
+    pub fn ends_with(self, suffix: &str) {
+        let s = self.v.as_ref();
+        let expected = || format!("a string ending with {suffix:?}");
+        self.check(s.ends_with(suffix), expected, &s);
+    }
+    /// The regex has to match somewhere in the string, use `^` and `$` to match all of it.
+    /// Needs the `regex` feature.
+    #[cfg(feature = "regex")]
+    #[track_caller]
+    pub fn matches_regex(self, regex: &str) {
+        use regex::Regex;
+        use std::collections::HashMap;
+
+        thread_local! {
+            /// Compiled patterns, as tests tend to check many strings against the same one.
+            static CACHE: RefCell<HashMap<String, Regex>> = RefCell::default();
+        }
+
+        let s = self.v.as_ref();
+        let compiled = CACHE.with_borrow_mut(|cache| match cache.get(regex) {
+            Some(compiled) => Ok(compiled.clone()),
+            None => Regex::new(regex).inspect(|compiled| {
+                cache.insert(regex.to_owned(), compiled.clone());
+            }),
+        });
+        match compiled {
+            Ok(compiled) => {
+                let ok = compiled.is_match(s);
+                self.check(ok, || format!("a string matching /{regex}/"), &s);
+            }
+            Err(err) => self.fail(format!("invalid regex /{regex}/: {err}")),
+        }
+    }
+}
+
+/// Matchers for floats, and anything else that converts to `f64` without losing precision.
+impl<T: Into<f64> + Copy + Debug> Assert<T> {
+    /// The value is at most `epsilon` away from `other`.
+    #[track_caller]
+    pub fn is_close_to(self, other: f64, epsilon: f64) {
+        let expected = || format!("a value within {epsilon:?} of {other:?}");
+        let ok = (self.v.into() - other).abs() <= epsilon;
+        self.check(ok, expected, &self.v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_bool() {
+        assert(true).is_true();
+        assert(false).is_false();
+    }
+
+    #[test]
+    fn assert_equal() {
+        assert(1).equals(1);
+        assert(2).not_equals(1);
+    }
+
+    #[test]
+    fn assert_str() {
+        assert("uwu owo").contains("uwu");
+        assert("uwu owo".to_owned()).contains("uwu");
+        assert("uwu owo").starts_with("uwu");
+        assert("uwu owo").ends_with("owo");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn assert_regex() {
+        assert("uwu owo").matches_regex(r"^u\wu o+wo$");
+        assert("uwu owo".to_owned()).matches_regex(r"^u\wu o+wo$");
+
+        let message = panic_message(|| assert("uwu").matches_regex("o+"));
+        assert(message).equals("expected: a string matching /o+/\n   found: \"uwu\"");
+        let message = panic_message(|| assert("uwu").matches_regex("(u"));
+        assert(message).starts_with("invalid regex /(u/: ");
+    }
+
+    #[test]
+    fn assert_ordering() {
+        assert(2).is_greater_than(1);
+        assert(1).is_less_than(2);
+        assert("b").is_between("a", "c");
+        assert(1).is_between(1, 1);
+        assert(0.1 + 0.2).is_close_to(0.3, 1e-9);
+        assert(1.0f32).is_close_to(1.1, 0.2);
+    }
+
+    #[test]
+    fn assert_option_result() {
+        assert(Some(1)).is_some();
+        assert(None::<i32>).is_none();
+        assert(Some(5)).is_some_and(|&v| v > 3);
+        assert(Ok::<_, ()>(1)).is_ok();
+        assert(Err::<(), _>("nope")).is_err();
+        assert(Ok::<_, ()>(5)).is_ok_and(|&v| v > 3);
+        assert(Err::<(), _>("nope")).is_err_and(|err| err.contains("no"));
+    }
+
+    #[test]
+    fn assert_collections() {
+        let v = vec![1, 2, 2, 3];
+        assert(&v).has_len(4);
+        assert(&v).contains_item(&2);
+        assert(&v).contains_exactly_in_any_order([&2, &3, &1, &2]);
+        assert(&v).is_sorted();
+        assert(Vec::<i32>::new()).is_empty();
+        assert([3, 1]).contains_exactly_in_any_order([1, 3]);
+    }
+
+    #[test]
+    #[should_panic = "expected: exactly [1, 1, 3] in any order\n   found: [3, 1, 3]"]
+    fn assert_collections_counts_items() {
+        assert([3, 1, 3]).contains_exactly_in_any_order([1, 1, 3]);
+    }
+
+    #[test]
+    #[should_panic = "values differ (- expected, + found), first at `[2]`"]
+    fn any_order_shows_diff() {
+        let found = (0..20).rev().filter(|&i| i != 2).chain([42]);
+        assert(found).contains_exactly_in_any_order(0..20);
+    }
+
+    #[test]
+    #[should_panic = "values differ (- expected, + found), first at `[2]`"]
+    fn equals_shows_diff() {
+        assert((0..20).collect::<Vec<_>>()).equals((0..20).filter(|&i| i != 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic = "expected: a value between 1 and 3\n   found: 4"]
+    fn failure_message() {
+        assert(4).is_between(1, 3);
+    }
+
+    pub(super) fn panic_message(f: impl FnOnce()) -> String {
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_err();
+        *payload.downcast::<String>().unwrap()
+    }
+
+    #[test]
+    fn soft_assertions() {
+        soft(|s| {
+            s.that(true).is_true();
+            s.that(vec![1, 2]).contains_item(2);
+        });
+
+        let line = line!();
+        let message = panic_message(|| {
+            soft(|s| {
+                s.that(1).equals(2);
+                s.that(true).is_true();
+                s.that([1, 2]).has_len(3);
+            })
+        });
+        let file = file!();
+        assert(&message).starts_with("2 soft assertion(s) failed:");
+        assert(&message).contains(&format!("{file}:{}:", line + 3));
+        assert(&message).contains("expected: 2\n   found: 1");
+        assert(&message).contains(&format!("{file}:{}:", line + 5));
+        assert(&message).contains("expected: a collection of length 3\n   found: [1, 2]");
+
+        // A hard failure inside `soft` keeps its own message.
+        let message = panic_message(|| {
+            soft(|s| {
+                s.that(1).equals(2);
+                assert(1).equals(3);
+            })
+        });
+        assert(message).equals("expected: 3\n   found: 1");
+    }
+}
@@ -0,0 +1,91 @@
+//! Assertions on code that should panic.
+
+use super::{diff, Assert};
+use std::{
+    any::Any,
+    fmt::{self, Debug},
+    panic::{self, AssertUnwindSafe},
+};
+
+/// The payload of a panic caught by [`assert_panics`].
+pub struct Panic {
+    payload: Box<dyn Any + Send>,
+}
+
+impl Panic {
+    /// The message of the panic, if it was a string, which it is for all panics from `panic!`.
+    pub fn message(&self) -> Option<&str> {
+        match self.payload.downcast_ref::<String>() {
+            Some(message) => Some(message),
+            None => self.payload.downcast_ref::<&str>().copied(),
+        }
+    }
+
+    pub fn payload(&self) -> &(dyn Any + Send) {
+        &*self.payload
+    }
+}
+
+impl Debug for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.message() {
+            Some(message) => f.debug_tuple("Panic").field(&message).finish(),
+            None => f.write_str("Panic(<non-string payload>)"),
+        }
+    }
+}
+
+/// Check that `f` panics, and return the payload for further checks.
+///
+/// ```
+/// use old_stuff::assert::assert_panics;
+///
+/// assert_panics(|| u8::MAX + std::hint::black_box(1)).with_message_containing("overflow");
+/// ```
+#[track_caller]
+pub fn assert_panics<T: Debug>(f: impl FnOnce() -> T) -> Assert<Panic> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => panic!("expected: a panic\n   found: {}", diff::show(&value)),
+        Err(payload) => Assert {
+            v: Panic { payload },
+            soft: None,
+        },
+    }
+}
+
+impl Assert<Panic> {
+    #[track_caller]
+    pub fn with_message_containing(self, other: &str) {
+        let expected = || format!("a panic with a message containing {other:?}");
+        let ok = self
+            .v
+            .message()
+            .is_some_and(|message| message.contains(other));
+        self.check(ok, expected, &self.v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert::{assert, predicate, tests::panic_message};
+
+    #[test]
+    fn panics() {
+        assert_panics(|| u8::MAX + std::hint::black_box(1)).with_message_containing("overflow");
+        assert_panics(|| panic::panic_any(7))
+            .satisfies(predicate("a panic with 7", |p: &Panic| {
+                p.payload().downcast_ref() == Some(&7)
+            }));
+
+        let message = panic_message(|| {
+            assert_panics(|| 1);
+        });
+        assert(message).equals("expected: a panic\n   found: 1");
+        let message =
+            panic_message(|| assert_panics(|| panic!("uwu")).with_message_containing("owo"));
+        let expected =
+            "expected: a panic with a message containing \"owo\"\n   found: Panic(\"uwu\")";
+        assert(message).equals(expected);
+    }
+}
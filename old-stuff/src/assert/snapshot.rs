@@ -0,0 +1,188 @@
+//! Snapshot testing: comparing a value with its formatted output from an earlier run.
+//!
+//! Snapshots are stored in a `snapshots` directory next to the file of the test, in
+//! `<file stem>__<name>.snap`. The path of that file is relative to the root of the workspace,
+//! while `cargo test` runs the tests in the root of the package, so it is looked up in the
+//! working directory and all directories above it. Run the tests with `UPDATE_SNAPSHOTS=1` to
+//! create missing snapshots and overwrite the ones that changed, then review the changes with
+//! git.
+
+use super::{diff, Assert};
+use std::{
+    fmt::Debug,
+    fs, io,
+    panic::Location,
+    path::{Path, PathBuf},
+};
+
+/// Whether snapshots should be rewritten instead of compared.
+fn update_snapshots() -> bool {
+    std::env::var_os("UPDATE_SNAPSHOTS").is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// The path of the snapshot `name` for the test in `file`, looking for `file` in `cwd` and the
+/// directories above it. If it isn't found, the path is relative to `cwd`.
+fn snapshot_path(file: &str, cwd: &Path, name: &str) -> PathBuf {
+    let file = cwd
+        .ancestors()
+        .map(|dir| dir.join(file))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(file));
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let dir = file.parent().unwrap_or(Path::new(""));
+    dir.join("snapshots").join(format!("{stem}__{name}.snap"))
+}
+
+/// Remove the indentation shared by all non-blank lines, and the line breaks directly after the
+/// opening and before the closing quote of an inline snapshot.
+fn dedent(snapshot: &str) -> String {
+    let snapshot = snapshot.strip_prefix('\n').unwrap_or(snapshot);
+    let snapshot = snapshot.trim_end_matches([' ', '\t']);
+    let snapshot = snapshot.strip_suffix('\n').unwrap_or(snapshot);
+    let indent = snapshot
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let lines = snapshot
+        .lines()
+        .map(|line| line.get(indent..).unwrap_or(""));
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+impl<T> Assert<T> {
+    /// Compare the pretty-printed `Debug` output of the value with the snapshot `name`.
+    #[track_caller]
+    pub fn matches_snapshot(self, name: &str)
+    where
+        T: Debug,
+    {
+        self.matches_snapshot_with(name, |v| format!("{v:#?}"));
+    }
+
+    /// Compare the value, formatted with `format`, with the snapshot `name`.
+    #[track_caller]
+    pub fn matches_snapshot_with(self, name: &str, format: impl FnOnce(&T) -> String) {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let path = snapshot_path(Location::caller().file(), &cwd, name);
+        let text = format(&self.v);
+        self.check_snapshot(&path, &text, update_snapshots());
+    }
+
+    /// Compare the pretty-printed `Debug` output of the value with `snapshot`. Indentation
+    /// shared by all lines is ignored, so the snapshot can be indented like the code around it.
+    ///
+    /// Inline snapshots are never rewritten, so `UPDATE_SNAPSHOTS` has no effect on them.
+    /// Paste the new output from the failure message instead.
+    #[track_caller]
+    pub fn matches_inline_snapshot(self, snapshot: &str)
+    where
+        T: Debug,
+    {
+        let text = format!("{:#?}", self.v);
+        let snapshot = dedent(snapshot);
+        if text != snapshot {
+            let diff = diff::diff_text(&snapshot, &text, diff::use_color());
+            self.fail(format!("inline snapshot doesn't match\n{diff}"));
+        }
+    }
+
+    #[track_caller]
+    fn check_snapshot(&self, path: &Path, text: &str, update: bool) {
+        let snapshot = match fs::read_to_string(path) {
+            Ok(snapshot) => Some(snapshot.replace("\r\n", "\n")),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => panic!("failed to read snapshot {}: {err}", path.display()),
+        };
+        let snapshot = snapshot
+            .as_deref()
+            .map(|s| s.strip_suffix('\n').unwrap_or(s));
+        if snapshot == Some(text) {
+            return;
+        }
+
+        if update {
+            let write = fs::create_dir_all(path.parent().unwrap_or(Path::new("")))
+                .and_then(|()| fs::write(path, format!("{text}\n")));
+            if let Err(err) = write {
+                panic!("failed to write snapshot {}: {err}", path.display());
+            }
+        } else if let Some(snapshot) = snapshot {
+            let diff = diff::diff_text(snapshot, text, diff::use_color());
+            self.fail(format!(
+                "snapshot {} doesn't match, run with UPDATE_SNAPSHOTS=1 to update it\n{diff}",
+                path.display(),
+            ));
+        } else {
+            self.fail(format!(
+                "snapshot {} doesn't exist, run with UPDATE_SNAPSHOTS=1 to create it\n{text}",
+                path.display(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert::{assert, soft, tests::panic_message};
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn snapshot_file() {
+        assert(vec![Point { x: 1, y: 2 }]).matches_snapshot("points");
+        assert("uwu").matches_snapshot_with("custom", |s| s.to_uppercase());
+    }
+
+    #[test]
+    fn inline_snapshot() {
+        assert(Point { x: 1, y: 2 }).matches_inline_snapshot(
+            "
+            Point {
+                x: 1,
+                y: 2,
+            }
+            ",
+        );
+        let message = panic_message(|| assert(1).matches_inline_snapshot("2"));
+        assert(message).starts_with("inline snapshot doesn't match");
+    }
+
+    #[test]
+    fn snapshot_path_in_workspace_member() {
+        let workspace = std::env::temp_dir().join(format!("snapshot-ws-{}", std::process::id()));
+        let member = workspace.join("member");
+        fs::create_dir_all(member.join("src")).unwrap();
+        fs::write(member.join("src").join("lib.rs"), "").unwrap();
+
+        let path = snapshot_path("member/src/lib.rs", &member, "name");
+        assert(path).equals(member.join("src/snapshots/lib__name.snap"));
+        let path = snapshot_path("src/lib.rs", &member, "name");
+        assert(path).equals(member.join("src/snapshots/lib__name.snap"));
+        fs::remove_dir_all(&workspace).unwrap();
+    }
+
+    #[test]
+    fn update_and_compare() {
+        let dir = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        let path = dir.join("snapshots").join("test__point.snap");
+        let _ = fs::remove_dir_all(&dir);
+
+        let message = panic_message(|| assert(()).check_snapshot(&path, "a\nb", false));
+        assert(message).contains("doesn't exist, run with UPDATE_SNAPSHOTS=1 to create it");
+        assert(()).check_snapshot(&path, "a\nb", true);
+        assert(fs::read_to_string(&path).unwrap()).equals("a\nb\n");
+        assert(()).check_snapshot(&path, "a\nb", false);
+
+        let message = panic_message(|| soft(|s| s.that(()).check_snapshot(&path, "a\nc", false)));
+        assert(message).contains("doesn't match, run with UPDATE_SNAPSHOTS=1 to update it");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,340 @@
+//! A hash map with separate chaining: every bucket is a vector of all entries hashing to it.
+//!
+//! The chains are allocated with the allocator of the map as well. Since they grow on
+//! insertion, [`HashMap::try_reserve`] only makes room in the table of chains.
+
+use super::{capacity_overflow, handle_reserve, HashMap, HashMapFamily};
+use std::{
+    alloc::{Allocator, Global},
+    collections::TryReserveError,
+    hash::{BuildHasher, Hash, RandomState},
+    iter, vec,
+};
+
+/// The number of buckets of the first allocation.
+const MIN_BUCKETS: usize = 8;
+
+type Buckets<K, V, A> = Vec<Vec<(K, V), A>, A>;
+
+pub struct ChainedHashMap<K, V, S = RandomState, A: Allocator = Global> {
+    buckets: Buckets<K, V, A>,
+    len: usize,
+    s: S,
+}
+
+impl<K: Eq + Hash, V> ChainedHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> ChainedHashMap<K, V, S> {
+    pub fn with_hasher(state: S) -> Self {
+        HashMap::with_hasher_in(state, Global)
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> ChainedHashMap<K, V, S, A> {
+    /// A table of `len` empty chains, which don't allocate until they are used.
+    fn try_empty_buckets(&self, len: usize) -> Result<Buckets<K, V, A>, TryReserveError> {
+        let alloc = self.buckets.allocator();
+        let mut buckets = Vec::new_in(alloc.clone());
+        buckets.try_reserve_exact(len)?;
+        buckets.resize_with(len, || Vec::new_in(alloc.clone()));
+        Ok(buckets)
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, A: Allocator + Clone> ChainedHashMap<K, V, S, A> {
+    fn bucket_of_elem(&self, key: &K) -> usize {
+        assert_ne!(self.buckets.len(), 0, "cannot compute bucket of empty map");
+        self.s.hash_one(key) as usize & (self.buckets.len() - 1)
+    }
+
+    fn chain_of(&self, key: &K) -> &[(K, V)] {
+        if self.buckets.is_empty() {
+            return &[];
+        }
+        &self.buckets[self.bucket_of_elem(key)]
+    }
+
+    /// Move all entries into a table of `new` buckets.
+    fn try_resize(&mut self, new: usize) -> Result<(), TryReserveError> {
+        let mut buckets = self.try_empty_buckets(new)?;
+        let mask = new - 1;
+        // Allocate all chains before moving any entries, so that a failed allocation
+        // leaves the map as it was.
+        let mut lens = Vec::new_in(self.buckets.allocator().clone());
+        lens.try_reserve_exact(new)?;
+        lens.resize(new, 0);
+        for (key, _) in self.buckets.iter().flatten() {
+            lens[self.s.hash_one(key) as usize & mask] += 1;
+        }
+        for (chain, len) in buckets.iter_mut().zip(lens) {
+            chain.try_reserve_exact(len)?;
+        }
+
+        let old = std::mem::replace(&mut self.buckets, buckets);
+        for (key, value) in old.into_iter().flatten() {
+            let bucket = self.bucket_of_elem(&key);
+            self.buckets[bucket].push((key, value));
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> HashMap<K, V, S, A> for ChainedHashMap<K, V, S, A> {
+    type Iter<'a>
+        = Iter<'a, K, V, A>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V, A>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            buckets: Vec::new_in(alloc),
+            len: 0,
+            s: state,
+        }
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        let mut map = Self::with_hasher_in(state, alloc);
+        if capacity > 0 {
+            let buckets = handle_reserve(
+                capacity
+                    .checked_next_power_of_two()
+                    .ok_or_else(capacity_overflow),
+            );
+            map.buckets = handle_reserve(map.try_empty_buckets(buckets.max(MIN_BUCKETS)));
+        }
+        map
+    }
+
+    fn allocator(&self) -> &A {
+        self.buckets.allocator()
+    }
+
+    fn hasher(&self) -> &S {
+        &self.s
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.chain_of(key)
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map(|(_, value)| value)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let bucket = self.bucket_of_elem(key);
+        self.buckets[bucket]
+            .iter_mut()
+            .find(|(elem_key, _)| elem_key == key)
+            .map(|(_, value)| value)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        handle_reserve(self.try_insert(key, value))
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let bucket = self.bucket_of_elem(key);
+        let chain = &mut self.buckets[bucket];
+        let pos = chain.iter().position(|(elem_key, _)| elem_key == key)?;
+        self.len -= 1;
+        Some(chain.swap_remove(pos).1)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        // Grow once there is more than one entry per bucket on average.
+        let required = self
+            .len
+            .checked_add(additional)
+            .ok_or_else(capacity_overflow)?;
+        if required <= self.buckets.len() {
+            return Ok(());
+        }
+        let new = required
+            .checked_next_power_of_two()
+            .ok_or_else(capacity_overflow)?;
+        self.try_resize(new.max(MIN_BUCKETS))
+    }
+
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        // Overwriting an entry mustn't grow the table, so look for the key first.
+        if !self.buckets.is_empty() {
+            let bucket = self.bucket_of_elem(&key);
+            let chain = &mut self.buckets[bucket];
+            if let Some((_, old)) = chain.iter_mut().find(|(elem_key, _)| *elem_key == key) {
+                return Ok(Some(std::mem::replace(old, value)));
+            }
+        }
+        self.try_reserve(1)?;
+        let bucket = self.bucket_of_elem(&key);
+        let chain = &mut self.buckets[bucket];
+        chain.try_reserve(1)?;
+        chain.push((key, value));
+        self.len += 1;
+        Ok(None)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            buckets: self.buckets.iter().flatten(),
+            remaining: self.len,
+        }
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        IterMut {
+            buckets: self.buckets.iter_mut().flatten(),
+            remaining: self.len,
+        }
+    }
+}
+
+impl_std_traits!(ChainedHashMap);
+
+pub struct Iter<'a, K, V, A: Allocator = Global> {
+    buckets: iter::Flatten<std::slice::Iter<'a, Vec<(K, V), A>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V, A: Allocator> Iterator for Iter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.buckets.next()?;
+        let entry = (key, value);
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V, A: Allocator> ExactSizeIterator for Iter<'_, K, V, A> {}
+
+pub struct IterMut<'a, K, V, A: Allocator = Global> {
+    buckets: iter::Flatten<std::slice::IterMut<'a, Vec<(K, V), A>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V, A: Allocator> Iterator for IterMut<'a, K, V, A> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.buckets.next()?;
+        let entry = (&*key, value);
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V, A: Allocator> ExactSizeIterator for IterMut<'_, K, V, A> {}
+
+type IntoIterInner<K, V, A> = iter::Flatten<vec::IntoIter<Vec<(K, V), A>, A>>;
+
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    buckets: IntoIterInner<K, V, A>,
+}
+
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buckets.next()
+    }
+}
+
+impl<K, V, S, A: Allocator> IntoIterator for ChainedHashMap<K, V, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            buckets: self.buckets.into_iter().flatten(),
+        }
+    }
+}
+
+pub struct ChainedHashMapFamily;
+impl HashMapFamily for ChainedHashMapFamily {
+    type Map<K, V, S, A: Allocator + Clone> = ChainedHashMap<K, V, S, A>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChainedHashMap;
+    use crate::hashmaps::HashMap;
+    use std::hash::RandomState;
+
+    #[test]
+    fn do_tests() {
+        crate::hashmaps::tests::run_tests::<super::ChainedHashMapFamily>();
+    }
+
+    #[test]
+    fn overwriting_doesnt_grow() {
+        let mut m: ChainedHashMap<_, _> =
+            ChainedHashMap::with_capacity_and_hasher(8, RandomState::new());
+        let buckets = m.buckets.len();
+        for i in 0..buckets {
+            m.insert(i, i);
+        }
+        assert_eq!(m.try_insert(0, 1), Ok(Some(0)));
+        assert_eq!(m.buckets.len(), buckets);
+    }
+
+    #[test]
+    #[should_panic = "capacity overflow"]
+    fn with_capacity_overflow() {
+        ChainedHashMap::<u8, u8>::with_capacity_and_hasher(usize::MAX, RandomState::new());
+    }
+}
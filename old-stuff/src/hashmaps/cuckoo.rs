@@ -0,0 +1,452 @@
+//! A cuckoo hash map: every key can only live in one of two buckets.
+//!
+//! The two bucket indices come from a single hash of the key (and a seed), so only one
+//! `BuildHasher` is needed. Lookups check exactly two buckets. Inserting into a full bucket
+//! evicts its entry, which then moves to its other bucket, possibly evicting another entry
+//! and so on. If this does not settle down after [`MAX_DISPLACEMENTS`] evictions, we assume
+//! a cycle. The homeless entry is put into a small stash, and once the stash overflows,
+//! the table is rehashed with a new seed, which gives every key two new buckets.
+//!
+//! With a degenerate hasher, rehashing does not help, so the stash is allowed to grow
+//! after a rehash that did not empty it. Lookups then degrade to a linear scan of the stash.
+//!
+//! A rehash allocates the new table and room for a full stash before it moves any entries,
+//! so running out of memory there leaves the map unchanged. Only if more entries than that
+//! end up in the stash does growing it abort on allocation failure, like pushing to a `Vec`.
+//! [`HashMap::try_insert`] never rehashes just because the stash overflowed, it leaves that
+//! to a later insertion.
+
+use super::{capacity_overflow, handle_reserve, HashMap, HashMapFamily};
+use std::{
+    alloc::{Allocator, Global},
+    collections::TryReserveError,
+    hash::{BuildHasher, Hash, Hasher, RandomState},
+    vec,
+};
+
+/// The number of buckets of the first allocation.
+const MIN_BUCKETS: usize = 8;
+/// The number of evictions after which we give up placing an entry.
+const MAX_DISPLACEMENTS: usize = 32;
+/// The number of entries the stash may hold before we rehash.
+const MIN_STASH: usize = 4;
+
+pub struct CuckooHashMap<K, V, S = RandomState, A: Allocator = Global> {
+    buckets: Vec<Option<(K, V)>, A>,
+    /// Entries that did not find a place in `buckets`.
+    stash: Vec<(K, V), A>,
+    max_stash: usize,
+    len: usize,
+    /// Mixed into every hash, changed on every rehash.
+    seed: u64,
+    s: S,
+}
+
+impl<K: Eq + Hash, V> CuckooHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> CuckooHashMap<K, V, S> {
+    pub fn with_hasher(state: S) -> Self {
+        HashMap::with_hasher_in(state, Global)
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, A: Allocator + Clone> CuckooHashMap<K, V, S, A> {
+    /// The two buckets `key` may be in.
+    fn buckets_of_elem(&self, key: &K) -> [usize; 2] {
+        assert_ne!(self.buckets.len(), 0, "cannot compute bucket of empty map");
+        let mut hasher = self.s.build_hasher();
+        hasher.write_u64(self.seed);
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mask = self.buckets.len() - 1;
+        // Derive the second index from the upper bits, which the first one doesn't use.
+        let second = hash.rotate_left(32).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        [hash as usize & mask, second as usize & mask]
+    }
+
+    fn find(&self, key: &K) -> Option<&(K, V)> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        self.buckets_of_elem(key)
+            .into_iter()
+            .filter_map(|bucket| self.buckets[bucket].as_ref())
+            .chain(&self.stash)
+            .find(|(elem_key, _)| elem_key == key)
+    }
+
+    fn find_mut(&mut self, key: &K) -> Option<&mut (K, V)> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let [first, second] = self.buckets_of_elem(key);
+        let is_key =
+            |entry: &Option<(K, V)>| matches!(entry, Some((elem_key, _)) if elem_key == key);
+        if is_key(&self.buckets[first]) {
+            self.buckets[first].as_mut()
+        } else if is_key(&self.buckets[second]) {
+            self.buckets[second].as_mut()
+        } else {
+            self.stash.iter_mut().find(|(elem_key, _)| elem_key == key)
+        }
+    }
+
+    /// Place an entry into the table by evicting other entries.
+    /// Returns the entry that is left without a bucket if there seems to be a cycle.
+    fn place(&mut self, mut entry: (K, V)) -> Result<(), (K, V)> {
+        let mut bucket = self.buckets_of_elem(&entry.0)[0];
+        for _ in 0..MAX_DISPLACEMENTS {
+            match &mut self.buckets[bucket] {
+                slot @ None => {
+                    *slot = Some(entry);
+                    return Ok(());
+                }
+                Some(existing) => std::mem::swap(existing, &mut entry),
+            }
+            // Move the evicted entry to its other bucket.
+            let [first, second] = self.buckets_of_elem(&entry.0);
+            bucket = if bucket == first { second } else { first };
+        }
+        Err(entry)
+    }
+
+    /// Place an entry that is known not to be in the map yet.
+    fn insert_new(&mut self, entry: (K, V)) {
+        let Err(homeless) = self.place(entry) else {
+            return;
+        };
+        self.stash.push(homeless);
+        if self.stash.len() > self.max_stash {
+            self.rehash(self.buckets.len());
+        }
+    }
+
+    /// Reinsert all entries into `new` buckets with new hash functions.
+    fn rehash(&mut self, new: usize) {
+        handle_reserve(self.try_rehash(new));
+    }
+
+    fn try_rehash(&mut self, new: usize) -> Result<(), TryReserveError> {
+        let alloc = self.buckets.allocator();
+        let mut buckets = Vec::new_in(alloc.clone());
+        buckets.try_reserve_exact(new)?;
+        buckets.resize_with(new, || None);
+        let mut stash = Vec::new_in(alloc.clone());
+        stash.try_reserve_exact(self.max_stash + 1)?;
+
+        let old_buckets = std::mem::replace(&mut self.buckets, buckets);
+        let old_stash = std::mem::replace(&mut self.stash, stash);
+        self.seed = self.seed.wrapping_add(1);
+
+        for entry in old_buckets.into_iter().flatten().chain(old_stash) {
+            if let Err(homeless) = self.place(entry) {
+                self.stash.push(homeless);
+            }
+        }
+        // If the new hash functions didn't manage to empty the stash, another rehash
+        // probably won't either, so let the stash grow instead of rehashing all the time.
+        self.max_stash = MIN_STASH.max(self.stash.len() * 2);
+        Ok(())
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> HashMap<K, V, S, A> for CuckooHashMap<K, V, S, A> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            buckets: Vec::new_in(alloc.clone()),
+            stash: Vec::new_in(alloc),
+            max_stash: MIN_STASH,
+            len: 0,
+            seed: 0,
+            s: state,
+        }
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        let mut map = Self::with_hasher_in(state, alloc);
+        if capacity > 0 {
+            let buckets = capacity
+                .checked_mul(2)
+                .and_then(usize::checked_next_power_of_two)
+                .ok_or_else(capacity_overflow);
+            let buckets = handle_reserve(buckets).max(MIN_BUCKETS);
+            handle_reserve(map.buckets.try_reserve_exact(buckets));
+            map.buckets.resize_with(buckets, || None);
+        }
+        map
+    }
+
+    fn allocator(&self) -> &A {
+        self.buckets.allocator()
+    }
+
+    fn hasher(&self) -> &S {
+        &self.s
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.find(key).map(|(_, value)| value)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.find_mut(key).map(|(_, value)| value)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if let Some((_, old)) = self.find_mut(&key) {
+            return Some(std::mem::replace(old, value));
+        }
+
+        // With two choices per key, cuckoo hashing only works well up to a load of 1/2.
+        if (self.len + 1) * 2 > self.buckets.len() {
+            let new = (self.buckets.len() * 2).max(MIN_BUCKETS);
+            self.rehash(new);
+        }
+        self.insert_new((key, value));
+        self.len += 1;
+        None
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let removed = self
+            .buckets_of_elem(key)
+            .into_iter()
+            .find(|&bucket| matches!(&self.buckets[bucket], Some((elem_key, _)) if elem_key == key))
+            .and_then(|bucket| self.buckets[bucket].take())
+            .or_else(|| {
+                let pos = self
+                    .stash
+                    .iter()
+                    .position(|(elem_key, _)| elem_key == key)?;
+                Some(self.stash.swap_remove(pos))
+            })?;
+        self.len -= 1;
+        Some(removed.1)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let required = self
+            .len
+            .checked_add(additional)
+            .and_then(|len| len.checked_mul(2))
+            .ok_or_else(capacity_overflow)?;
+        if required > self.buckets.len() {
+            let new = required
+                .checked_next_power_of_two()
+                .ok_or_else(capacity_overflow)?;
+            self.try_rehash(new.max(MIN_BUCKETS))?;
+        }
+        // Room for entries that end up in the stash until it overflows.
+        let stash_room = (self.max_stash + 1).saturating_sub(self.stash.len());
+        self.stash.try_reserve(stash_room.min(additional))
+    }
+
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if let Some((_, old)) = self.find_mut(&key) {
+            return Ok(Some(std::mem::replace(old, value)));
+        }
+        self.try_reserve(1)?;
+        // Placing the entry may evict another one, so there has to be room in the stash.
+        self.stash.try_reserve(1)?;
+        if let Err(homeless) = self.place((key, value)) {
+            self.stash.push(homeless);
+        }
+        self.len += 1;
+        Ok(None)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            buckets: self.buckets.iter().flatten().chain(&self.stash),
+            remaining: self.len,
+        }
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        IterMut {
+            buckets: self.buckets.iter_mut().flatten().chain(&mut self.stash),
+            remaining: self.len,
+        }
+    }
+}
+
+impl_std_traits!(CuckooHashMap);
+
+type IterInner<'a, K, V> = std::iter::Chain<
+    std::iter::Flatten<std::slice::Iter<'a, Option<(K, V)>>>,
+    std::slice::Iter<'a, (K, V)>,
+>;
+
+type IterMutInner<'a, K, V> = std::iter::Chain<
+    std::iter::Flatten<std::slice::IterMut<'a, Option<(K, V)>>>,
+    std::slice::IterMut<'a, (K, V)>,
+>;
+
+pub struct Iter<'a, K, V> {
+    buckets: IterInner<'a, K, V>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.buckets.next()?;
+        let entry = (key, value);
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub struct IterMut<'a, K, V> {
+    buckets: IterMutInner<'a, K, V>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.buckets.next()?;
+        let entry = (&*key, value);
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
+type IntoIterInner<K, V, A> = std::iter::Chain<
+    std::iter::Flatten<vec::IntoIter<Option<(K, V)>, A>>,
+    vec::IntoIter<(K, V), A>,
+>;
+
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    buckets: IntoIterInner<K, V, A>,
+}
+
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buckets.next()
+    }
+}
+
+impl<K, V, S, A: Allocator> IntoIterator for CuckooHashMap<K, V, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            buckets: self.buckets.into_iter().flatten().chain(self.stash),
+        }
+    }
+}
+
+pub struct CuckooHashMapFamily;
+impl HashMapFamily for CuckooHashMapFamily {
+    type Map<K, V, S, A: Allocator + Clone> = CuckooHashMap<K, V, S, A>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    use super::CuckooHashMap;
+    use crate::hashmaps::HashMap;
+
+    #[test]
+    fn do_tests() {
+        crate::hashmaps::tests::run_tests::<super::CuckooHashMapFamily>();
+    }
+
+    #[test]
+    fn cycles_go_to_the_stash() {
+        #[derive(Default)]
+        struct CollidingHasher;
+        impl Hasher for CollidingHasher {
+            fn finish(&self) -> u64 {
+                0
+            }
+            fn write(&mut self, _bytes: &[u8]) {}
+        }
+
+        let mut m = CuckooHashMap::with_hasher(BuildHasherDefault::<CollidingHasher>::default());
+        // Both buckets of every key are the same, so only one of them can be placed.
+        for i in 0..3 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.stash.len(), 2);
+        assert_eq!(m.seed, 1);
+
+        for i in 3..10 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.stash.len(), 9);
+        assert!(
+            m.seed > 1,
+            "overflowing the stash should have caused a rehash"
+        );
+        for i in 0..10 {
+            assert_eq!(m.get(&i), Some(&i));
+        }
+    }
+}
@@ -0,0 +1,935 @@
+//! A persistent hash array mapped trie (HAMT).
+//!
+//! Every level of the trie uses the next 5 bits of the hash to pick one of 32 children.
+//! A branch only stores the children that exist, along with a bitmap of which ones those are,
+//! so the index of a child is the number of set bits below its bit. Two keys with the same
+//! 64 bit hash end up in a collision node, which is just a list.
+//!
+//! Nodes are reference counted and shared between clones of a map, so cloning is O(1).
+//! Modifying a map copies the nodes on the path to the modified entry if they are shared,
+//! so the other versions are not affected.
+//!
+//! The [`HashMap`] trait doesn't require `K: Clone` and `V: Clone` to insert, but copying
+//! a shared node needs them. Nodes only get shared when a map is cloned, which does require
+//! them, so cloning stores a function to clone entries in both maps for later copies.
+//!
+//! Every insertion allocates, so there is nothing to reserve ahead of time. Instead,
+//! [`HashMap::try_insert`] allocates everything a step needs before changing the trie.
+//! A copy of a shared node is as good as the original, so running out of memory halfway
+//! down the path leaves a map with the same entries.
+
+use super::{
+    alloc_error, capacity_overflow, handle_reserve, HashMap, HashMapFamily, PersistentHashMap,
+};
+use std::{
+    alloc::{Allocator, Global, Layout},
+    collections::TryReserveError,
+    hash::{BuildHasher, Hash, RandomState},
+    slice,
+    sync::{Arc, OnceLock},
+    vec,
+};
+
+/// The number of hash bits used per level.
+const BITS: u32 = 5;
+
+type CloneEntry<K, V> = fn(&K, &V) -> (K, V);
+
+enum Child<K, V, A: Allocator> {
+    Leaf { hash: u64, key: K, value: V },
+    Node(Arc<Node<K, V, A>, A>),
+}
+
+enum Node<K, V, A: Allocator> {
+    Branch {
+        bitmap: u32,
+        children: Vec<Child<K, V, A>, A>,
+    },
+    /// Entries whose keys all have the same `hash`.
+    Collision { hash: u64, entries: Vec<(K, V), A> },
+}
+
+/// A merge that ran out of memory, with the children that were to be merged.
+type MergeError<K, V, A> = (TryReserveError, Child<K, V, A>, Child<K, V, A>);
+
+/// The index into a branch at `depth` for `hash`.
+fn chunk(hash: u64, depth: u32) -> u32 {
+    (hash.checked_shr(depth * BITS).unwrap_or(0) & ((1 << BITS) - 1)) as u32
+}
+
+/// A vector with room for exactly `capacity` elements.
+fn try_vec_in<T, A: Allocator>(capacity: usize, alloc: A) -> Result<Vec<T, A>, TryReserveError> {
+    let mut vec = Vec::new_in(alloc);
+    vec.try_reserve_exact(capacity)?;
+    Ok(vec)
+}
+
+fn try_arc_in<K, V, A: Allocator>(
+    node: Node<K, V, A>,
+    alloc: A,
+) -> Result<Arc<Node<K, V, A>, A>, TryReserveError> {
+    Arc::try_new_in(node, alloc).map_err(|_| alloc_error(Layout::new::<Node<K, V, A>>()))
+}
+
+impl<K, V, A: Allocator> Child<K, V, A> {
+    /// The hash of every key in this child. Only meaningful for leaves and collision nodes.
+    fn hash(&self) -> u64 {
+        match self {
+            Child::Leaf { hash, .. } => *hash,
+            Child::Node(node) => match &**node {
+                Node::Collision { hash, .. } => *hash,
+                Node::Branch { .. } => unreachable!("branches don't have a single hash"),
+            },
+        }
+    }
+
+    /// The number of entries in this child. Only meaningful for leaves and collision nodes.
+    fn entries(&self) -> usize {
+        match self {
+            Child::Leaf { .. } => 1,
+            Child::Node(node) => match &**node {
+                Node::Collision { entries, .. } => entries.len(),
+                Node::Branch { .. } => unreachable!("branches are never merged"),
+            },
+        }
+    }
+}
+
+impl<K, V, A: Allocator + Clone> Node<K, V, A> {
+    fn empty(alloc: A) -> Self {
+        Node::Branch {
+            bitmap: 0,
+            children: Vec::new_in(alloc),
+        }
+    }
+
+    /// A copy of this node that shares all its children.
+    fn try_clone_with(
+        &self,
+        clone_entry: CloneEntry<K, V>,
+        alloc: A,
+    ) -> Result<Self, TryReserveError> {
+        Ok(match self {
+            Node::Branch { bitmap, children } => {
+                let mut copy = try_vec_in(children.len(), alloc)?;
+                copy.extend(children.iter().map(|child| match child {
+                    Child::Leaf { hash, key, value } => {
+                        let (key, value) = clone_entry(key, value);
+                        Child::Leaf {
+                            hash: *hash,
+                            key,
+                            value,
+                        }
+                    }
+                    Child::Node(node) => Child::Node(node.clone()),
+                }));
+                Node::Branch {
+                    bitmap: *bitmap,
+                    children: copy,
+                }
+            }
+            Node::Collision { hash, entries } => {
+                let mut copy = try_vec_in(entries.len(), alloc)?;
+                copy.extend(entries.iter().map(|(key, value)| clone_entry(key, value)));
+                Node::Collision {
+                    hash: *hash,
+                    entries: copy,
+                }
+            }
+        })
+    }
+
+    /// A node for two children that ended up at the same index at `depth - 1`.
+    /// Both are leaves or collision nodes. All memory is allocated before `a` and `b`
+    /// are taken apart, so they can be given back if that fails.
+    fn try_merge(
+        a: Child<K, V, A>,
+        b: Child<K, V, A>,
+        depth: u32,
+        alloc: A,
+    ) -> Result<Arc<Self, A>, MergeError<K, V, A>> {
+        let mut arc = match Arc::try_new_uninit_in(alloc.clone()) {
+            Ok(arc) => arc,
+            Err(_) => return Err((alloc_error(Layout::new::<Self>()), a, b)),
+        };
+
+        let (hash_a, hash_b) = (a.hash(), b.hash());
+        let node = if hash_a == hash_b {
+            let mut entries = match try_vec_in(a.entries() + b.entries(), alloc) {
+                Ok(entries) => entries,
+                Err(err) => return Err((err, a, b)),
+            };
+            for child in [a, b] {
+                match child {
+                    Child::Leaf { key, value, .. } => entries.push((key, value)),
+                    Child::Node(node) => match Arc::try_unwrap(node) {
+                        Ok(Node::Collision { entries: more, .. }) => entries.extend(more),
+                        _ => unreachable!("only unshared collision nodes are merged"),
+                    },
+                }
+            }
+            Node::Collision {
+                hash: hash_a,
+                entries,
+            }
+        } else {
+            let (chunk_a, chunk_b) = (chunk(hash_a, depth), chunk(hash_b, depth));
+            let len = if chunk_a == chunk_b { 1 } else { 2 };
+            let mut children = match try_vec_in(len, alloc.clone()) {
+                Ok(children) => children,
+                Err(err) => return Err((err, a, b)),
+            };
+            if chunk_a == chunk_b {
+                children.push(Child::Node(Self::try_merge(a, b, depth + 1, alloc)?));
+            } else if chunk_a < chunk_b {
+                children.extend([a, b]);
+            } else {
+                children.extend([b, a]);
+            }
+            Node::Branch {
+                bitmap: (1 << chunk_a) | (1 << chunk_b),
+                children,
+            }
+        };
+
+        Arc::get_mut(&mut arc)
+            .expect("new node is unique")
+            .write(node);
+        // SAFETY: The node was just written.
+        Ok(unsafe { arc.assume_init() })
+    }
+}
+
+impl<K, V, A: Allocator> Node<K, V, A> {
+    /// Take the only entry of this node, if it has just one, as a child that can be
+    /// stored at any depth.
+    fn take_single(&mut self) -> Option<Child<K, V, A>> {
+        match self {
+            Node::Branch { children, .. } if children.len() == 1 => match &children[0] {
+                Child::Leaf { .. } => children.pop(),
+                Child::Node(node) if matches!(**node, Node::Collision { .. }) => children.pop(),
+                Child::Node(_) => None,
+            },
+            Node::Collision { hash, entries } if entries.len() == 1 => {
+                let (key, value) = entries.pop()?;
+                Some(Child::Leaf {
+                    hash: *hash,
+                    key,
+                    value,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Get mutable access to a node, copying it first if it is shared.
+fn make_mut<K, V, A: Allocator + Clone>(
+    node: &mut Arc<Node<K, V, A>, A>,
+    clone_entry: Option<CloneEntry<K, V>>,
+) -> &mut Node<K, V, A> {
+    handle_reserve(try_make_mut(node, clone_entry))
+}
+
+fn try_make_mut<K, V, A: Allocator + Clone>(
+    node: &mut Arc<Node<K, V, A>, A>,
+    clone_entry: Option<CloneEntry<K, V>>,
+) -> Result<&mut Node<K, V, A>, TryReserveError> {
+    if Arc::get_mut(node).is_none() {
+        let clone_entry = clone_entry.expect("shared node in a map that was never cloned");
+        let alloc = Arc::allocator(node).clone();
+        let copy = node.try_clone_with(clone_entry, alloc.clone())?;
+        *node = try_arc_in(copy, alloc)?;
+    }
+    Ok(Arc::get_mut(node).expect("node was just unshared"))
+}
+
+/// Copy all shared nodes, so that every node of the trie can be modified in place.
+fn unshare_all<K, V, A: Allocator + Clone>(
+    node: &mut Arc<Node<K, V, A>, A>,
+    clone_entry: Option<CloneEntry<K, V>>,
+) {
+    if let Node::Branch { children, .. } = make_mut(node, clone_entry) {
+        for child in children {
+            if let Child::Node(node) = child {
+                unshare_all(node, clone_entry);
+            }
+        }
+    }
+}
+
+impl<K: Eq, V, A: Allocator + Clone> Node<K, V, A> {
+    fn get(&self, hash: u64, key: &K) -> Option<&V> {
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let bit = 1 << chunk(hash, depth);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &children[(bitmap & (bit - 1)).count_ones() as usize] {
+                        Child::Leaf {
+                            hash: leaf_hash,
+                            key: leaf_key,
+                            value,
+                        } => return (*leaf_hash == hash && leaf_key == key).then_some(value),
+                        Child::Node(child) => node = child,
+                    }
+                    depth += 1;
+                }
+                Node::Collision {
+                    hash: node_hash,
+                    entries,
+                } => {
+                    if *node_hash != hash {
+                        return None;
+                    }
+                    return entries
+                        .iter()
+                        .find(|(entry_key, _)| entry_key == key)
+                        .map(|(_, value)| value);
+                }
+            }
+        }
+    }
+
+    /// Like [`Node::get`], copying the shared nodes on the way to the entry.
+    fn get_mut(
+        &mut self,
+        depth: u32,
+        hash: u64,
+        key: &K,
+        clone_entry: Option<CloneEntry<K, V>>,
+    ) -> Option<&mut V> {
+        match self {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << chunk(hash, depth);
+                if *bitmap & bit == 0 {
+                    return None;
+                }
+                match &mut children[(*bitmap & (bit - 1)).count_ones() as usize] {
+                    Child::Leaf {
+                        hash: leaf_hash,
+                        key: leaf_key,
+                        value,
+                    } => (*leaf_hash == hash && leaf_key == key).then_some(value),
+                    Child::Node(node) => {
+                        make_mut(node, clone_entry).get_mut(depth + 1, hash, key, clone_entry)
+                    }
+                }
+            }
+            Node::Collision {
+                hash: node_hash,
+                entries,
+            } => {
+                if *node_hash != hash {
+                    return None;
+                }
+                entries
+                    .iter_mut()
+                    .find(|(entry_key, _)| entry_key == key)
+                    .map(|(_, value)| value)
+            }
+        }
+    }
+
+    /// Insert into a branch at `depth`. Collision nodes are handled by their parent.
+    fn try_insert(
+        &mut self,
+        depth: u32,
+        hash: u64,
+        key: K,
+        value: V,
+        clone_entry: Option<CloneEntry<K, V>>,
+    ) -> Result<Option<V>, TryReserveError> {
+        let Node::Branch { bitmap, children } = self else {
+            unreachable!("insert into a collision node");
+        };
+        let bit = 1 << chunk(hash, depth);
+        let index = (*bitmap & (bit - 1)).count_ones() as usize;
+        if *bitmap & bit == 0 {
+            children.try_reserve(1)?;
+            *bitmap |= bit;
+            children.insert(index, Child::Leaf { hash, key, value });
+            return Ok(None);
+        }
+
+        match &mut children[index] {
+            Child::Leaf {
+                hash: leaf_hash,
+                key: leaf_key,
+                value: leaf_value,
+            } if *leaf_hash == hash && *leaf_key == key => {
+                return Ok(Some(std::mem::replace(leaf_value, value)));
+            }
+            Child::Node(node) => {
+                let node = try_make_mut(node, clone_entry)?;
+                match node {
+                    Node::Branch { .. } => {
+                        return node.try_insert(depth + 1, hash, key, value, clone_entry);
+                    }
+                    Node::Collision {
+                        hash: node_hash,
+                        entries,
+                    } if *node_hash == hash => {
+                        if let Some((_, old)) = entries.iter_mut().find(|(k, _)| *k == key) {
+                            return Ok(Some(std::mem::replace(old, value)));
+                        }
+                        entries.try_reserve(1)?;
+                        entries.push((key, value));
+                        return Ok(None);
+                    }
+                    Node::Collision { .. } => {}
+                }
+            }
+            Child::Leaf { .. } => {}
+        }
+
+        // A different key is in the way, so both move down into a new node. Removing the
+        // existing child keeps its slot allocated, so it can always be put back.
+        let existing = children.remove(index);
+        let new = Child::Leaf { hash, key, value };
+        let alloc = children.allocator().clone();
+        match Self::try_merge(existing, new, depth + 1, alloc) {
+            Ok(merged) => {
+                children.insert(index, Child::Node(merged));
+                Ok(None)
+            }
+            Err((err, existing, _)) => {
+                children.insert(index, existing);
+                Err(err)
+            }
+        }
+    }
+
+    fn remove(
+        &mut self,
+        depth: u32,
+        hash: u64,
+        key: &K,
+        clone_entry: Option<CloneEntry<K, V>>,
+    ) -> Option<V> {
+        match self {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << chunk(hash, depth);
+                if *bitmap & bit == 0 {
+                    return None;
+                }
+                let index = (*bitmap & (bit - 1)).count_ones() as usize;
+                match &mut children[index] {
+                    Child::Leaf {
+                        hash: leaf_hash,
+                        key: leaf_key,
+                        ..
+                    } => {
+                        if *leaf_hash != hash || leaf_key != key {
+                            return None;
+                        }
+                        *bitmap &= !bit;
+                        let Child::Leaf { value, .. } = children.remove(index) else {
+                            unreachable!()
+                        };
+                        Some(value)
+                    }
+                    Child::Node(node) => {
+                        let node = make_mut(node, clone_entry);
+                        let removed = node.remove(depth + 1, hash, key, clone_entry)?;
+                        // Don't keep a whole node around for a single entry.
+                        if let Some(single) = node.take_single() {
+                            children[index] = single;
+                        }
+                        Some(removed)
+                    }
+                }
+            }
+            Node::Collision {
+                hash: node_hash,
+                entries,
+            } => {
+                if *node_hash != hash {
+                    return None;
+                }
+                let pos = entries.iter().position(|(entry_key, _)| entry_key == key)?;
+                Some(entries.swap_remove(pos).1)
+            }
+        }
+    }
+}
+
+pub struct HamtHashMap<K, V, S = RandomState, A: Allocator = Global> {
+    root: Arc<Node<K, V, A>, A>,
+    len: usize,
+    /// Set once nodes of this map may be shared with another map.
+    clone_entry: OnceLock<CloneEntry<K, V>>,
+    s: S,
+}
+
+impl<K: Eq + Hash, V> HamtHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> HamtHashMap<K, V, S> {
+    pub fn with_hasher(state: S) -> Self {
+        HashMap::with_hasher_in(state, Global)
+    }
+}
+
+impl<K, V, S, A: Allocator> HamtHashMap<K, V, S, A> {
+    fn clone_entry(&self) -> Option<CloneEntry<K, V>> {
+        self.clone_entry.get().copied()
+    }
+
+    /// Whether both maps are versions that still share their root, so they have the same
+    /// entries without having to compare them.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> HashMap<K, V, S, A> for HamtHashMap<K, V, S, A> {
+    type Iter<'a>
+        = Iter<'a, K, V, A>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V, A>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            root: Arc::new_in(Node::empty(alloc.clone()), alloc),
+            len: 0,
+            clone_entry: OnceLock::new(),
+            s: state,
+        }
+    }
+
+    fn allocator(&self) -> &A {
+        Arc::allocator(&self.root)
+    }
+
+    fn hasher(&self) -> &S {
+        &self.s
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.root.get(self.s.hash_one(key), key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let hash = self.s.hash_one(key);
+        // Don't copy shared nodes if there is nothing to change.
+        self.root.get(hash, key)?;
+        let clone_entry = self.clone_entry();
+        make_mut(&mut self.root, clone_entry).get_mut(0, hash, key, clone_entry)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        handle_reserve(self.try_insert(key, value))
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let hash = self.s.hash_one(key);
+        // Don't copy shared nodes if there is nothing to remove.
+        self.root.get(hash, key)?;
+        let clone_entry = self.clone_entry();
+        let removed = make_mut(&mut self.root, clone_entry).remove(0, hash, key, clone_entry);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.len
+            .checked_add(additional)
+            .ok_or_else(capacity_overflow)?;
+        Ok(())
+    }
+
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let hash = self.s.hash_one(&key);
+        let clone_entry = self.clone_entry();
+        let root = try_make_mut(&mut self.root, clone_entry)?;
+        let old = root.try_insert(0, hash, key, value, clone_entry)?;
+        if old.is_none() {
+            self.len += 1;
+        }
+        Ok(old)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            stack: vec![NodeIter::new(&self.root)],
+            remaining: self.len,
+        }
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        let clone_entry = self.clone_entry();
+        unshare_all(&mut self.root, clone_entry);
+        let root = Arc::get_mut(&mut self.root).expect("root was just unshared");
+        IterMut {
+            stack: vec![NodeIterMut::new(root)],
+            remaining: self.len,
+        }
+    }
+}
+
+impl<K: Clone, V: Clone, S: Clone, A: Allocator + Clone> Clone for HamtHashMap<K, V, S, A> {
+    fn clone(&self) -> Self {
+        let clone_entry: CloneEntry<K, V> = |key, value| (key.clone(), value.clone());
+        self.clone_entry.get_or_init(|| clone_entry);
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+            clone_entry: OnceLock::from(clone_entry),
+            s: self.s.clone(),
+        }
+    }
+}
+
+impl<K, V, S, A> PersistentHashMap<K, V, S, A> for HamtHashMap<K, V, S, A>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+    A: Allocator + Clone,
+{
+}
+
+impl_std_traits!(@no_clone HamtHashMap);
+
+enum NodeIter<'a, K, V, A: Allocator> {
+    Branch(slice::Iter<'a, Child<K, V, A>>),
+    Collision(slice::Iter<'a, (K, V)>),
+}
+
+impl<'a, K, V, A: Allocator> NodeIter<'a, K, V, A> {
+    fn new(node: &'a Node<K, V, A>) -> Self {
+        match node {
+            Node::Branch { children, .. } => NodeIter::Branch(children.iter()),
+            Node::Collision { entries, .. } => NodeIter::Collision(entries.iter()),
+        }
+    }
+}
+
+pub struct Iter<'a, K, V, A: Allocator = Global> {
+    stack: Vec<NodeIter<'a, K, V, A>>,
+    remaining: usize,
+}
+
+impl<'a, K, V, A: Allocator> Iterator for Iter<'a, K, V, A> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.stack.last_mut()? {
+                NodeIter::Branch(children) => match children.next() {
+                    Some(Child::Leaf { key, value, .. }) => Some((key, value)),
+                    Some(Child::Node(node)) => {
+                        self.stack.push(NodeIter::new(node));
+                        None
+                    }
+                    None => {
+                        self.stack.pop();
+                        None
+                    }
+                },
+                NodeIter::Collision(entries) => match entries.next() {
+                    Some((key, value)) => Some((key, value)),
+                    None => {
+                        self.stack.pop();
+                        None
+                    }
+                },
+            };
+            if let Some(entry) = entry {
+                self.remaining -= 1;
+                return Some(entry);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V, A: Allocator> ExactSizeIterator for Iter<'_, K, V, A> {}
+
+enum NodeIterMut<'a, K, V, A: Allocator> {
+    Branch(slice::IterMut<'a, Child<K, V, A>>),
+    Collision(slice::IterMut<'a, (K, V)>),
+}
+
+impl<'a, K, V, A: Allocator> NodeIterMut<'a, K, V, A> {
+    fn new(node: &'a mut Node<K, V, A>) -> Self {
+        match node {
+            Node::Branch { children, .. } => NodeIterMut::Branch(children.iter_mut()),
+            Node::Collision { entries, .. } => NodeIterMut::Collision(entries.iter_mut()),
+        }
+    }
+}
+
+pub struct IterMut<'a, K, V, A: Allocator = Global> {
+    stack: Vec<NodeIterMut<'a, K, V, A>>,
+    remaining: usize,
+}
+
+impl<'a, K, V, A: Allocator> Iterator for IterMut<'a, K, V, A> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.stack.last_mut()? {
+                NodeIterMut::Branch(children) => match children.next() {
+                    Some(Child::Leaf { key, value, .. }) => Some((&*key, value)),
+                    Some(Child::Node(node)) => {
+                        let node = Arc::get_mut(node).expect("iter_mut unshared all nodes");
+                        self.stack.push(NodeIterMut::new(node));
+                        None
+                    }
+                    None => {
+                        self.stack.pop();
+                        None
+                    }
+                },
+                NodeIterMut::Collision(entries) => match entries.next() {
+                    Some((key, value)) => Some((&*key, value)),
+                    None => {
+                        self.stack.pop();
+                        None
+                    }
+                },
+            };
+            if let Some(entry) = entry {
+                self.remaining -= 1;
+                return Some(entry);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V, A: Allocator> ExactSizeIterator for IterMut<'_, K, V, A> {}
+
+enum NodeIntoIter<K, V, A: Allocator> {
+    Branch(vec::IntoIter<Child<K, V, A>, A>),
+    Collision(vec::IntoIter<(K, V), A>),
+}
+
+impl<K, V, A: Allocator + Clone> NodeIntoIter<K, V, A> {
+    /// Iterate over the entries of `node`, copying it if it is still shared.
+    fn new(node: Arc<Node<K, V, A>, A>, clone_entry: Option<CloneEntry<K, V>>) -> Self {
+        let node = Arc::try_unwrap(node).unwrap_or_else(|node| {
+            let clone_entry = clone_entry.expect("shared node in a map that was never cloned");
+            handle_reserve(node.try_clone_with(clone_entry, Arc::allocator(&node).clone()))
+        });
+        match node {
+            Node::Branch { children, .. } => NodeIntoIter::Branch(children.into_iter()),
+            Node::Collision { entries, .. } => NodeIntoIter::Collision(entries.into_iter()),
+        }
+    }
+}
+
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    stack: Vec<NodeIntoIter<K, V, A>>,
+    clone_entry: Option<CloneEntry<K, V>>,
+}
+
+impl<K, V, A: Allocator + Clone> Iterator for IntoIter<K, V, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.stack.last_mut()? {
+                NodeIntoIter::Branch(children) => match children.next() {
+                    Some(Child::Leaf { key, value, .. }) => Some((key, value)),
+                    Some(Child::Node(node)) => {
+                        let node = NodeIntoIter::new(node, self.clone_entry);
+                        self.stack.push(node);
+                        None
+                    }
+                    None => {
+                        self.stack.pop();
+                        None
+                    }
+                },
+                NodeIntoIter::Collision(entries) => match entries.next() {
+                    Some(entry) => Some(entry),
+                    None => {
+                        self.stack.pop();
+                        None
+                    }
+                },
+            };
+            if entry.is_some() {
+                return entry;
+            }
+        }
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> IntoIterator for HamtHashMap<K, V, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let clone_entry = self.clone_entry();
+        IntoIter {
+            stack: vec![NodeIntoIter::new(self.root, clone_entry)],
+            clone_entry,
+        }
+    }
+}
+
+pub struct HamtHashMapFamily;
+impl HashMapFamily for HamtHashMapFamily {
+    type Map<K, V, S, A: Allocator + Clone> = HamtHashMap<K, V, S, A>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    use super::HamtHashMap;
+    use crate::hashmaps::{HashMap, PersistentHashMap};
+
+    #[test]
+    fn do_tests() {
+        crate::hashmaps::tests::run_tests::<super::HamtHashMapFamily>();
+    }
+
+    #[test]
+    fn versions_are_independent() {
+        let empty = HamtHashMap::new();
+        let one = empty.insert_new(1, "one");
+        let two = one.insert_new(2, "two");
+        let changed = two.insert_new(1, "uno");
+        let removed = changed.remove_new(&2);
+
+        assert!(empty.is_empty());
+        assert_eq!(one.get(&1), Some(&"one"));
+        assert_eq!(one.get(&2), None);
+        assert_eq!((two[&1], two[&2]), ("one", "two"));
+        assert_eq!((changed[&1], changed[&2]), ("uno", "two"));
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[&1], "uno");
+        assert_eq!(two.len(), 2);
+    }
+
+    #[test]
+    fn clone_shares_structure() {
+        let mut m = (0..1000).map(|i| (i, i)).collect::<HamtHashMap<_, _>>();
+        let snapshot = m.clone();
+        assert!(m.ptr_eq(&snapshot));
+
+        m.insert(1000, 1000);
+        m.remove(&0);
+        for (_, value) in m.iter_mut() {
+            *value += 1;
+        }
+        assert!(!m.ptr_eq(&snapshot));
+        assert_eq!(snapshot.len(), 1000);
+        assert!((0..1000).all(|i| snapshot.get(&i) == Some(&i)));
+        assert_eq!(m.len(), 1000);
+        assert!((1..=1000).all(|i| m.get(&i) == Some(&(i + 1))));
+
+        // Consuming a version copies what it shares with the other.
+        let mut entries = snapshot.clone().into_iter().collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, (0..1000).map(|i| (i, i)).collect::<Vec<_>>());
+        assert_eq!(snapshot.len(), 1000);
+    }
+
+    /// Uses the key as the hash, to build specific tries.
+    #[derive(Default)]
+    struct IdentityHasher(u64);
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+        fn write(&mut self, _bytes: &[u8]) {
+            unreachable!("only write_u64 is used")
+        }
+        fn write_u64(&mut self, i: u64) {
+            self.0 = i;
+        }
+    }
+
+    #[derive(PartialEq, Eq, Clone, Debug)]
+    struct Key(u64, &'static str);
+    impl std::hash::Hash for Key {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            state.write_u64(self.0);
+        }
+    }
+
+    #[test]
+    fn collisions_and_collapsing() {
+        let mut m = HamtHashMap::with_hasher(BuildHasherDefault::<IdentityHasher>::default());
+        // The same first 55 bits, so they only split at the last level.
+        let deep = 1 << 60;
+        m.insert(Key(0, "a"), 1);
+        m.insert(Key(0, "b"), 2);
+        m.insert(Key(deep, "c"), 3);
+        let snapshot = m.clone();
+        m.insert(Key(0, "d"), 4);
+        assert_eq!(m.len(), 4);
+        assert_eq!(m[&Key(0, "b")], 2);
+        assert_eq!(m[&Key(deep, "c")], 3);
+        assert_eq!(m.get(&Key(deep, "a")), None);
+
+        m.remove(&Key(0, "a"));
+        m.remove(&Key(0, "b"));
+        m.remove(&Key(0, "d"));
+        // The remaining entry moves all the way back up to the root.
+        let super::Node::Branch { children, .. } = &*m.root else {
+            panic!("root is not a branch");
+        };
+        assert!(matches!(children[..], [super::Child::Leaf { .. }]));
+        assert_eq!(m.iter().collect::<Vec<_>>(), [(&Key(deep, "c"), &3)]);
+
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot[&Key(0, "a")], 1);
+    }
+}
@@ -0,0 +1,245 @@
+//! Tools for dealing with bad hash functions: [`HashStats`] to find out how well the keys of
+//! a map are distributed, and [`Reseed`] to recover from a bad distribution at runtime.
+//!
+//! A hash function can be bad by accident, or because an attacker picked keys that collide
+//! (HashDoS). Randomly seeded hashers like [`RandomState`] make the second case hard, but
+//! only if the seed changes once it is clear that the keys collide under the current one.
+//!
+//! The open addressing maps ([`SimpleOAHashMap`], [`RobinHoodHashMap`], [`SwissHashMap`],
+//! [`IndexedHashMap`] and [`SmallHashMap`] once it spilled) offer `stats()` and
+//! `enable_hashdos_protection()`. The other maps don't need them:
+//! - [`ChainedHashMap`] has no probe sequences. Colliding keys make long chains, but they
+//!   don't slow down the lookups of any other keys.
+//! - [`CuckooHashMap`] looks at no more than two buckets and the stash for every key, and
+//!   already switches to a new seed whenever an insertion can't find a place.
+//! - [`HamtHashMap`] is a trie of the hash bits. Keys with the same full hash share a
+//!   collision node, which doesn't affect any other keys.
+//!
+//! [`SimpleOAHashMap`]: super::simple_open_addressing::SimpleOAHashMap
+//! [`RobinHoodHashMap`]: super::robin_hood::RobinHoodHashMap
+//! [`SwissHashMap`]: super::swiss_table::SwissHashMap
+//! [`IndexedHashMap`]: super::indexed::IndexedHashMap
+//! [`SmallHashMap`]: super::small::SmallHashMap
+//! [`ChainedHashMap`]: super::chained::ChainedHashMap
+//! [`CuckooHashMap`]: super::cuckoo::CuckooHashMap
+//! [`HamtHashMap`]: super::hamt::HamtHashMap
+
+use std::{fmt, hash::RandomState};
+
+/// A hasher that can switch to a different, unpredictable hash function.
+pub trait Reseed {
+    fn reseed(&mut self);
+}
+
+impl Reseed for RandomState {
+    fn reseed(&mut self) {
+        *self = RandomState::new();
+    }
+}
+
+/// The longest probe sequence that is still considered normal for a table of `buckets` buckets.
+///
+/// With a good hash function, the longest probe sequence grows logarithmically with the size
+/// of the table. The factor leaves plenty of room for the long runs that linear probing
+/// naturally produces at high load.
+fn max_normal_probe_len(buckets: usize) -> usize {
+    16 * (buckets.max(1).ilog2() as usize + 1)
+}
+
+/// The HashDoS protection of a map, which switches its hasher to a new seed whenever an
+/// insertion has to probe much further than it would with a good hash function.
+///
+/// If the keys still collide with the new seed, they collide no matter the seed, and the
+/// protection turns itself off instead of making the map rehash over and over.
+pub(super) struct HashDosProtection<S> {
+    reseed: Option<fn(&mut S)>,
+}
+
+impl<S> HashDosProtection<S> {
+    pub(super) fn disabled() -> Self {
+        Self { reseed: None }
+    }
+
+    pub(super) fn enable(&mut self)
+    where
+        S: Reseed,
+    {
+        self.reseed = Some(S::reseed);
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.reseed.is_some()
+    }
+
+    /// Whether an insertion that probed `probe_len` buckets past its home bucket in a table of
+    /// `buckets` buckets should reseed the hasher.
+    pub(super) fn is_triggered(&self, probe_len: usize, buckets: usize) -> bool {
+        self.is_enabled() && probe_len > max_normal_probe_len(buckets)
+    }
+
+    /// Switch `state` to a new seed. The map has to rehash all of its entries afterwards, and
+    /// then pass its new stats to [`HashDosProtection::check_reseed`].
+    pub(super) fn reseed(&self, state: &mut S) {
+        if let Some(reseed) = self.reseed {
+            reseed(state);
+        }
+    }
+
+    /// Turn the protection off if reseeding didn't help.
+    pub(super) fn check_reseed(&mut self, stats: &HashStats) {
+        if stats.is_degenerate() {
+            self.reseed = None;
+        }
+    }
+}
+
+/// The distribution of the entries of an open addressing map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashStats {
+    pub len: usize,
+    pub buckets: usize,
+    /// `probe_lengths[n]` is the number of entries that are `n` buckets away from their
+    /// home bucket, so `probe_lengths[0]` is the number of entries in their home bucket.
+    pub probe_lengths: Vec<usize>,
+    /// The number of runs of consecutive occupied buckets.
+    pub clusters: usize,
+    /// The number of buckets in the longest run of occupied buckets.
+    pub max_cluster_len: usize,
+}
+
+impl HashStats {
+    /// `occupied` says for every bucket whether a probe sequence has to go past it,
+    /// `probe_lens` has the distance of every entry from its home bucket.
+    pub(super) fn new(
+        occupied: impl ExactSizeIterator<Item = bool>,
+        probe_lens: impl IntoIterator<Item = usize>,
+    ) -> Self {
+        let buckets = occupied.len();
+
+        let mut probe_lengths = Vec::new();
+        let mut len = 0;
+        for probe_len in probe_lens {
+            if probe_lengths.len() <= probe_len {
+                probe_lengths.resize(probe_len + 1, 0);
+            }
+            probe_lengths[probe_len] += 1;
+            len += 1;
+        }
+
+        let mut cluster_lens = Vec::new();
+        let mut current = 0;
+        let mut starts_occupied = false;
+        for (i, occupied) in occupied.enumerate() {
+            if occupied {
+                starts_occupied |= i == 0;
+                current += 1;
+            } else if current > 0 {
+                cluster_lens.push(current);
+                current = 0;
+            }
+        }
+        if current > 0 {
+            // Probing wraps around, so a cluster at the end continues the one at the start.
+            if starts_occupied && !cluster_lens.is_empty() {
+                cluster_lens[0] += current;
+            } else {
+                cluster_lens.push(current);
+            }
+        }
+
+        Self {
+            len,
+            buckets,
+            probe_lengths,
+            clusters: cluster_lens.len(),
+            max_cluster_len: cluster_lens.into_iter().max().unwrap_or(0),
+        }
+    }
+
+    /// The fraction of buckets that hold an entry.
+    pub fn load_factor(&self) -> f64 {
+        if self.buckets == 0 {
+            0.0
+        } else {
+            self.len as f64 / self.buckets as f64
+        }
+    }
+
+    pub fn max_probe_len(&self) -> usize {
+        self.probe_lengths.len().saturating_sub(1)
+    }
+
+    pub fn mean_probe_len(&self) -> f64 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        let total = self
+            .probe_lengths
+            .iter()
+            .enumerate()
+            .map(|(probe_len, &count)| probe_len * count)
+            .sum::<usize>();
+        total as f64 / self.len as f64
+    }
+
+    /// Whether the probe sequences are much longer than a good hash function would produce.
+    pub fn is_degenerate(&self) -> bool {
+        self.max_probe_len() > max_normal_probe_len(self.buckets)
+    }
+}
+
+impl fmt::Display for HashStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} entries in {} buckets (load {:.2})",
+            self.len,
+            self.buckets,
+            self.load_factor()
+        )?;
+        writeln!(
+            f,
+            "probe length: mean {:.2}, max {}",
+            self.mean_probe_len(),
+            self.max_probe_len()
+        )?;
+        writeln!(
+            f,
+            "clusters: {}, longest {}",
+            self.clusters, self.max_cluster_len
+        )?;
+        for (probe_len, &count) in self.probe_lengths.iter().enumerate() {
+            if count > 0 {
+                writeln!(f, "{probe_len:>6}: {count}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashStats;
+
+    #[test]
+    fn histogram_and_clusters() {
+        let occupied = [true, false, true, true, false, false, true, true];
+        let stats = HashStats::new(occupied.into_iter(), [0, 0, 1, 3, 0]);
+        assert_eq!(stats.len, 5);
+        assert_eq!(stats.probe_lengths, [3, 1, 0, 1]);
+        assert_eq!(stats.max_probe_len(), 3);
+        assert_eq!(stats.mean_probe_len(), 0.8);
+        // The cluster at the end wraps around to the start.
+        assert_eq!(stats.clusters, 2);
+        assert_eq!(stats.max_cluster_len, 3);
+        assert_eq!(stats.load_factor(), 5.0 / 8.0);
+        assert!(!stats.is_degenerate());
+
+        let full = HashStats::new([true; 4].into_iter(), [0, 1, 2, 3]);
+        assert_eq!((full.clusters, full.max_cluster_len), (1, 4));
+
+        let empty = HashStats::new(std::iter::empty(), []);
+        assert_eq!((empty.clusters, empty.max_probe_len()), (0, 0));
+        assert_eq!(empty.mean_probe_len(), 0.0);
+    }
+}
@@ -0,0 +1,537 @@
+//! A hash map that keeps its entries in insertion order.
+//!
+//! The entries are stored densely in a `Vec`, and an open addressing table maps every key to
+//! the position of its entry. The table is probed linearly just like the buckets of a
+//! [`SimpleOAHashMap`](super::simple_open_addressing::SimpleOAHashMap), and also uses
+//! tombstones for removed entries. Iterating only walks the `Vec`, so it is fast and always
+//! yields the entries in the order they were inserted.
+//!
+//! [`HashMap::remove`] is [`IndexedHashMap::swap_remove`], which moves the last entry into the
+//! gap. [`IndexedHashMap::shift_remove`] keeps the order of the remaining entries, but has to
+//! update the position of every entry behind the removed one.
+//!
+//! Like the simple open addressing map, it can detect keys that collide and switch to a new
+//! seed, see [`IndexedHashMap::enable_hashdos_protection`].
+
+use super::{
+    capacity_overflow, handle_reserve,
+    hash_quality::{HashDosProtection, HashStats, Reseed},
+    simple_open_addressing::probe_seq,
+    HashMap, HashMapFamily,
+};
+use std::{
+    alloc::{Allocator, Global},
+    collections::TryReserveError,
+    hash::{BuildHasher, Hash, RandomState},
+    slice, vec,
+};
+
+/// The number of slots of the first allocation.
+const MIN_SLOTS: usize = 8;
+
+/// The maximum load factor of the slots as `(numerator, denominator)`.
+const MAX_LOAD: (usize, usize) = (7, 8);
+
+#[derive(Clone, Copy)]
+enum Slot {
+    Empty,
+    /// The position of an entry.
+    Full(usize),
+    Tombstone,
+}
+
+/// The number of slots required to index `capacity` entries.
+fn slots_for(capacity: usize) -> Result<usize, TryReserveError> {
+    if capacity == 0 {
+        return Ok(0);
+    }
+    let (numerator, denominator) = MAX_LOAD;
+    // One more than the strict minimum, so that there is always an empty slot to end probing.
+    let required = capacity
+        .checked_mul(denominator)
+        .map(|slots| slots.div_ceil(numerator) + 1)
+        .and_then(usize::checked_next_power_of_two)
+        .ok_or_else(capacity_overflow)?;
+    Ok(required.max(MIN_SLOTS))
+}
+
+/// A table of `len` empty slots, allocated with `alloc`.
+fn try_empty_slots<A: Allocator>(len: usize, alloc: A) -> Result<Vec<Slot, A>, TryReserveError> {
+    let mut slots = Vec::new_in(alloc);
+    slots.try_reserve_exact(len)?;
+    slots.resize(len, Slot::Empty);
+    Ok(slots)
+}
+
+pub struct IndexedHashMap<K, V, S = RandomState, A: Allocator = Global> {
+    /// The entries in insertion order.
+    entries: Vec<(K, V), A>,
+    /// The positions of the entries in `entries`, found by the hash of their key.
+    slots: Vec<Slot, A>,
+    tombstones: usize,
+    protection: HashDosProtection<S>,
+    s: S,
+}
+
+impl<K: Eq + Hash, V> IndexedHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> IndexedHashMap<K, V, S> {
+    pub fn with_hasher(state: S) -> Self {
+        HashMap::with_hasher_in(state, Global)
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> IndexedHashMap<K, V, S, A> {
+    /// The entry at position `index` in insertion order.
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        self.entries.get(index).map(|(key, value)| (key, value))
+    }
+
+    /// The entry at position `index` in insertion order, with a mutable value.
+    pub fn get_index_mut(&mut self, index: usize) -> Option<(&K, &mut V)> {
+        self.entries
+            .get_mut(index)
+            .map(|(key, value)| (&*key, value))
+    }
+
+    /// Switch to a new hash seed and rebuild the slots whenever an insertion has to probe
+    /// much further than it would with a good hash function.
+    ///
+    /// If the keys still collide with the new seed, the map stops reseeding.
+    pub fn enable_hashdos_protection(&mut self)
+    where
+        S: Reseed,
+    {
+        self.protection.enable();
+    }
+
+    /// Whether inserting `additional` more entries would push the load of the slots over the
+    /// maximum load factor.
+    fn needs_grow(&self, additional: usize) -> bool {
+        let (numerator, denominator) = MAX_LOAD;
+        let used = (self.entries.len() + self.tombstones).saturating_add(additional);
+        used.saturating_mul(denominator) > self.slots.len() * numerator
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, A: Allocator + Clone> IndexedHashMap<K, V, S, A> {
+    fn home_slot(&self, key: &K) -> usize {
+        assert_ne!(self.slots.len(), 0, "cannot compute slot of empty map");
+        self.s.hash_one(key) as usize & (self.slots.len() - 1)
+    }
+
+    /// How well the keys are distributed over the slots.
+    pub fn stats(&self) -> HashStats {
+        let mask = self.slots.len().wrapping_sub(1);
+        let probe_lens = self.slots.iter().enumerate().filter_map(|(i, slot)| {
+            let Slot::Full(index) = *slot else {
+                return None;
+            };
+            let home = self.s.hash_one(&self.entries[index].0) as usize;
+            Some(i.wrapping_sub(home) & mask)
+        });
+        HashStats::new(
+            self.slots.iter().map(|slot| !matches!(slot, Slot::Empty)),
+            probe_lens,
+        )
+    }
+
+    /// The index of the slot pointing to the entry with `key`, if there is one.
+    fn find_slot(&self, key: &K) -> Option<usize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        probe_seq(self.home_slot(key), self.slots.len())
+            .take_while(|&i| !matches!(self.slots[i], Slot::Empty))
+            .find(|&i| matches!(self.slots[i], Slot::Full(index) if self.entries[index].0 == *key))
+    }
+
+    /// The index of the slot pointing to the entry at position `index`.
+    fn slot_of_index(&self, index: usize) -> usize {
+        probe_seq(self.home_slot(&self.entries[index].0), self.slots.len())
+            .find(|&i| matches!(self.slots[i], Slot::Full(other) if other == index))
+            .expect("entry is missing from the slots")
+    }
+
+    /// The position of the entry with `key` in insertion order.
+    pub fn get_index_of(&self, key: &K) -> Option<usize> {
+        match self.slots[self.find_slot(key)?] {
+            Slot::Full(index) => Some(index),
+            Slot::Empty | Slot::Tombstone => unreachable!("found a slot without an entry"),
+        }
+    }
+
+    /// Mark the slot of the entry with `key` as removed, and return the entry's position.
+    fn remove_slot(&mut self, key: &K) -> Option<usize> {
+        let slot = self.find_slot(key)?;
+        let Slot::Full(index) = std::mem::replace(&mut self.slots[slot], Slot::Tombstone) else {
+            unreachable!("found a slot without an entry");
+        };
+        self.tombstones += 1;
+        Some(index)
+    }
+
+    /// Remove the entry with `key` and put the last entry in its place. This is O(1), but
+    /// changes the order of the entries.
+    pub fn swap_remove(&mut self, key: &K) -> Option<V> {
+        let index = self.remove_slot(key)?;
+        let last = self.entries.len() - 1;
+        if index != last {
+            let slot = self.slot_of_index(last);
+            self.slots[slot] = Slot::Full(index);
+        }
+        Some(self.entries.swap_remove(index).1)
+    }
+
+    /// Remove the entry with `key` and move all entries after it one position forward. This
+    /// keeps the order of the entries, but is O(n).
+    pub fn shift_remove(&mut self, key: &K) -> Option<V> {
+        let index = self.remove_slot(key)?;
+        for slot in &mut self.slots {
+            match slot {
+                Slot::Full(other) if *other > index => *other -= 1,
+                Slot::Empty | Slot::Full(_) | Slot::Tombstone => {}
+            }
+        }
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Index all entries in a fresh table of `new` slots, dropping all tombstones.
+    fn try_rebuild(&mut self, new: usize) -> Result<(), TryReserveError> {
+        let slots = try_empty_slots(new, self.slots.allocator().clone())?;
+        self.index_into(slots);
+        Ok(())
+    }
+
+    /// Index all entries in `slots`, which must be empty, and use them as the new table.
+    fn index_into(&mut self, mut slots: Vec<Slot, A>) {
+        let mask = slots.len() - 1;
+        for (index, (key, _)) in self.entries.iter().enumerate() {
+            let home = self.s.hash_one(key) as usize & mask;
+            let slot = probe_seq(home, slots.len())
+                .find(|&i| matches!(slots[i], Slot::Empty))
+                .expect("no empty slot found in the table");
+            slots[slot] = Slot::Full(index);
+        }
+        self.slots = slots;
+        self.tombstones = 0;
+    }
+
+    fn reseed_and_rebuild(&mut self) {
+        // Allocate before reseeding, as the entries can't be found with the new seed until
+        // they are indexed again. Without the memory, the map just stays slow.
+        let Ok(slots) = try_empty_slots(self.slots.len(), self.slots.allocator().clone()) else {
+            return;
+        };
+        self.protection.reseed(&mut self.s);
+        self.index_into(slots);
+        let stats = self.stats();
+        self.protection.check_reseed(&stats);
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> HashMap<K, V, S, A> for IndexedHashMap<K, V, S, A> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            entries: Vec::new_in(alloc.clone()),
+            slots: Vec::new_in(alloc),
+            tombstones: 0,
+            protection: HashDosProtection::disabled(),
+            s: state,
+        }
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        let slots = handle_reserve(slots_for(capacity));
+        Self {
+            entries: Vec::with_capacity_in(capacity, alloc.clone()),
+            slots: handle_reserve(try_empty_slots(slots, alloc)),
+            tombstones: 0,
+            protection: HashDosProtection::disabled(),
+            s: state,
+        }
+    }
+
+    fn allocator(&self) -> &A {
+        self.entries.allocator()
+    }
+
+    fn hasher(&self) -> &S {
+        &self.s
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let index = self.get_index_of(key)?;
+        Some(&self.entries[index].1)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let index = self.get_index_of(key)?;
+        Some(&mut self.entries[index].1)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        handle_reserve(self.try_insert(key, value))
+    }
+
+    /// Same as [`IndexedHashMap::swap_remove`].
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.swap_remove(key)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.entries.try_reserve(additional)?;
+        if !self.needs_grow(additional) {
+            return Ok(());
+        }
+        // Rebuilding drops the tombstones, so only the entries need room in the new table.
+        let required = self
+            .entries
+            .len()
+            .checked_add(additional)
+            .ok_or_else(capacity_overflow)?;
+        let new = slots_for(required)?.max(self.slots.len());
+        self.try_rebuild(new)
+    }
+
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if let Some(index) = self.get_index_of(&key) {
+            return Ok(Some(std::mem::replace(&mut self.entries[index].1, value)));
+        }
+        self.try_reserve(1)?;
+
+        // The key is not in the map, so it can take the first free slot.
+        let (probe_len, slot) = probe_seq(self.home_slot(&key), self.slots.len())
+            .enumerate()
+            .find(|&(_, i)| !matches!(self.slots[i], Slot::Full(_)))
+            .expect("no free slot found in the table");
+        if let Slot::Tombstone = self.slots[slot] {
+            self.tombstones -= 1;
+        }
+        self.slots[slot] = Slot::Full(self.entries.len());
+        self.entries.push((key, value));
+        if self.protection.is_triggered(probe_len, self.slots.len()) {
+            self.reseed_and_rebuild();
+        }
+        Ok(None)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            entries: self.entries.iter(),
+        }
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        IterMut {
+            entries: self.entries.iter_mut(),
+        }
+    }
+}
+
+impl_std_traits!(IndexedHashMap);
+
+pub struct Iter<'a, K, V> {
+    entries: slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|(key, value)| (key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub struct IterMut<'a, K, V> {
+    entries: slice::IterMut<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|(key, value)| (&*key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    entries: vec::IntoIter<(K, V), A>,
+}
+
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<K, V, A: Allocator> ExactSizeIterator for IntoIter<K, V, A> {}
+
+impl<K, V, S, A: Allocator> IntoIterator for IndexedHashMap<K, V, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            entries: self.entries.into_iter(),
+        }
+    }
+}
+
+pub struct IndexedHashMapFamily;
+impl HashMapFamily for IndexedHashMapFamily {
+    type Map<K, V, S, A: Allocator + Clone> = IndexedHashMap<K, V, S, A>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashmaps::{tests::WeakSeedState, HashMap};
+
+    use super::IndexedHashMap;
+
+    #[test]
+    fn do_tests() {
+        crate::hashmaps::tests::run_tests::<super::IndexedHashMapFamily>();
+    }
+
+    fn keys(m: &IndexedHashMap<u32, u32>) -> Vec<u32> {
+        m.iter().map(|(&key, _)| key).collect()
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut m = IndexedHashMap::new();
+        for i in [5, 3, 8, 1, 9, 2] {
+            m.insert(i, i * 10);
+        }
+        // Replacing a value keeps its position.
+        m.insert(8, 0);
+        assert_eq!(keys(&m), [5, 3, 8, 1, 9, 2]);
+        assert_eq!(m.get_index(2), Some((&8, &0)));
+        assert_eq!(m.get_index_of(&9), Some(4));
+        assert_eq!(m.get_index(6), None);
+
+        assert_eq!(m.swap_remove(&3), Some(30));
+        assert_eq!(keys(&m), [5, 2, 8, 1, 9]);
+        assert_eq!(m.get_index_of(&2), Some(1));
+
+        assert_eq!(m.shift_remove(&8), Some(0));
+        assert_eq!(keys(&m), [5, 2, 1, 9]);
+        assert!([5, 2, 1, 9]
+            .iter()
+            .enumerate()
+            .all(|(index, key)| m.get_index_of(key) == Some(index)));
+
+        m.insert(3, 3);
+        assert_eq!(keys(&m), [5, 2, 1, 9, 3]);
+        assert_eq!(
+            m.into_iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            [5, 2, 1, 9, 3]
+        );
+    }
+
+    #[test]
+    fn removal_keeps_positions_in_sync() {
+        let mut m = IndexedHashMap::new();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        for i in (0..1000).step_by(3) {
+            m.swap_remove(&i);
+        }
+        for i in (1..1000).step_by(3) {
+            m.shift_remove(&i);
+        }
+        assert_eq!(m.len(), 333);
+        for index in 0..m.len() {
+            let (key, _) = m.get_index(index).unwrap();
+            assert_eq!(m.get_index_of(key), Some(index));
+        }
+    }
+
+    #[test]
+    fn hashdos_protection() {
+        let state = WeakSeedState {
+            seed: 0,
+            reseed_helps: true,
+        };
+        let mut m = IndexedHashMap::with_hasher(state);
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.hasher().seed, 1);
+        assert!(!m.stats().is_degenerate(), "{}", m.stats());
+        assert!((0..1000).all(|i| m.get_index_of(&i) == Some(i)));
+
+        let mut m = IndexedHashMap::with_hasher(WeakSeedState::default());
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert!(!m.protection.is_enabled());
+        assert!(m.stats().is_degenerate());
+        assert!((0..1000).all(|i| m.get_index_of(&i) == Some(i)));
+    }
+}
@@ -1,13 +1,271 @@
-use std::hash::{BuildHasher, Hash};
+use std::{
+    alloc::{handle_alloc_error, Allocator, Global, Layout},
+    collections::{TryReserveError, TryReserveErrorKind},
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+};
 
+/// Implements the standard traits for a map in terms of its [`HashMap`] implementation,
+/// so that every map gets them with a single line.
+///
+/// The map type must use `K`, `V`, `S` and `A` for its key, value, hasher and allocator
+/// parameters.
+/// Additional generic parameters can be passed in brackets before the type.
+/// Maps that can be cloned more cheaply than by inserting every entry into a new map
+/// can opt out of `Clone` with `@no_clone`.
+macro_rules! impl_std_traits {
+    ($map:ident) => {
+        impl_std_traits!([] $map<K, V, S, A>);
+    };
+    (@no_clone $map:ident) => {
+        impl_std_traits!(@no_clone [] $map<K, V, S, A>);
+    };
+    ([$($params:tt)*] $map:ty) => {
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::clone::Clone for $map
+        where
+            K: ::std::hash::Hash + ::std::cmp::Eq + ::std::clone::Clone,
+            V: ::std::clone::Clone,
+            S: ::std::hash::BuildHasher + ::std::clone::Clone,
+        {
+            fn clone(&self) -> Self {
+                let mut map = $crate::hashmaps::HashMap::with_hasher_in(
+                    $crate::hashmaps::HashMap::hasher(self).clone(),
+                    $crate::hashmaps::HashMap::allocator(self).clone(),
+                );
+                for (key, value) in $crate::hashmaps::HashMap::iter(self) {
+                    $crate::hashmaps::HashMap::insert(&mut map, key.clone(), value.clone());
+                }
+                map
+            }
+        }
+
+        impl_std_traits!(@no_clone [$($params)*] $map);
+    };
+    (@no_clone [$($params:tt)*] $map:ty) => {
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::fmt::Debug for $map
+        where
+            K: ::std::fmt::Debug,
+            V: ::std::fmt::Debug,
+        {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.debug_map()
+                    .entries($crate::hashmaps::HashMap::iter(self))
+                    .finish()
+            }
+        }
+
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::cmp::PartialEq for $map
+        where
+            K: ::std::hash::Hash + ::std::cmp::Eq,
+            V: ::std::cmp::PartialEq,
+            S: ::std::hash::BuildHasher,
+        {
+            fn eq(&self, other: &Self) -> bool {
+                $crate::hashmaps::HashMap::len(self) == $crate::hashmaps::HashMap::len(other)
+                    && $crate::hashmaps::HashMap::iter(self).all(|(key, value)| {
+                        $crate::hashmaps::HashMap::get(other, key) == Some(value)
+                    })
+            }
+        }
+
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::cmp::Eq for $map
+        where
+            K: ::std::hash::Hash + ::std::cmp::Eq,
+            V: ::std::cmp::Eq,
+            S: ::std::hash::BuildHasher,
+        {
+        }
+
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::default::Default for $map
+        where
+            S: ::std::default::Default,
+            A: ::std::default::Default,
+        {
+            fn default() -> Self {
+                $crate::hashmaps::HashMap::with_hasher_in(S::default(), A::default())
+            }
+        }
+
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::iter::Extend<(K, V)> for $map
+        where
+            K: ::std::hash::Hash + ::std::cmp::Eq,
+            S: ::std::hash::BuildHasher,
+        {
+            fn extend<T: ::std::iter::IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+                for (key, value) in iter {
+                    $crate::hashmaps::HashMap::insert(self, key, value);
+                }
+            }
+        }
+
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::iter::FromIterator<(K, V)> for $map
+        where
+            K: ::std::hash::Hash + ::std::cmp::Eq,
+            S: ::std::hash::BuildHasher + ::std::default::Default,
+            A: ::std::default::Default,
+        {
+            fn from_iter<T: ::std::iter::IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+                let mut map = <Self as ::std::default::Default>::default();
+                map.extend(iter);
+                map
+            }
+        }
+
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::ops::Index<&K> for $map
+        where
+            K: ::std::hash::Hash + ::std::cmp::Eq,
+            S: ::std::hash::BuildHasher,
+        {
+            type Output = V;
+
+            fn index(&self, key: &K) -> &V {
+                $crate::hashmaps::HashMap::get(self, key).expect("key not found in map")
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::serde::Serialize for $map
+        where
+            K: ::serde::Serialize,
+            V: ::serde::Serialize,
+        {
+            fn serialize<Ser: ::serde::Serializer>(
+                &self,
+                serializer: Ser,
+            ) -> ::std::result::Result<Ser::Ok, Ser::Error> {
+                serializer.collect_map($crate::hashmaps::HashMap::iter(self))
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> $crate::hashmaps::serde_impls::DeserializeMap<K, V, S> for $map
+        where
+            K: ::std::hash::Hash + ::std::cmp::Eq,
+            S: ::std::hash::BuildHasher,
+            A: ::std::default::Default,
+        {
+            fn with_capacity_and_hasher(capacity: usize, state: S) -> Self {
+                $crate::hashmaps::HashMap::with_capacity_and_hasher_in(capacity, state, A::default())
+            }
+
+            fn contains_key(&self, key: &K) -> bool {
+                $crate::hashmaps::HashMap::get(self, key).is_some()
+            }
+
+            fn insert(&mut self, key: K, value: V) {
+                $crate::hashmaps::HashMap::insert(self, key, value);
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de, K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::serde::Deserialize<'de> for $map
+        where
+            K: ::serde::Deserialize<'de> + ::std::hash::Hash + ::std::cmp::Eq,
+            V: ::serde::Deserialize<'de>,
+            S: ::std::hash::BuildHasher + ::std::default::Default,
+            A: ::std::default::Default,
+        {
+            fn deserialize<D: ::serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> ::std::result::Result<Self, D::Error> {
+                ::serde::de::DeserializeSeed::deserialize(
+                    $crate::hashmaps::serde_impls::MapSeed::new(
+                        $crate::hashmaps::serde_impls::DuplicateKeys::Error,
+                    ),
+                    deserializer,
+                )
+            }
+        }
+
+        impl<'a, K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::iter::IntoIterator for &'a $map {
+            type Item = (&'a K, &'a V);
+
+            type IntoIter = <$map as $crate::hashmaps::HashMap<K, V, S, A>>::Iter<'a>;
+
+            fn into_iter(self) -> Self::IntoIter {
+                $crate::hashmaps::HashMap::iter(self)
+            }
+        }
+
+        impl<'a, K, V, S, A: ::std::alloc::Allocator + ::std::clone::Clone, $($params)*> ::std::iter::IntoIterator for &'a mut $map {
+            type Item = (&'a K, &'a mut V);
+
+            type IntoIter = <$map as $crate::hashmaps::HashMap<K, V, S, A>>::IterMut<'a>;
+
+            fn into_iter(self) -> Self::IntoIter {
+                $crate::hashmaps::HashMap::iter_mut(self)
+            }
+        }
+    };
+}
+
+pub mod chained;
+pub mod cuckoo;
+pub mod hamt;
+pub mod hash_quality;
+pub mod indexed;
+#[cfg(test)]
+mod model_check;
+pub mod robin_hood;
+#[cfg(feature = "serde")]
+pub mod serde_impls;
+pub mod set;
+pub mod sharded;
 pub mod simple_open_addressing;
+pub mod small;
+pub mod swiss_table;
+
+pub use set::{HashSet, HashSetFamily};
 
 pub trait HashMapFamily {
-    type Map<K, V, S>: HashMap<K, V, S>;
+    type Map<K, V, S, A: Allocator + Clone>: HashMap<K, V, S, A>;
 }
 
-pub trait HashMap<K, V, S>: IntoIterator<Item = (K, V)> {
-    fn with_hasher(state: S) -> Self;
+/// A hash map with keys `K`, values `V`, hasher `S`, allocating all its memory with `A`.
+pub trait HashMap<K, V, S, A: Allocator = Global>: IntoIterator<Item = (K, V)> {
+    type Iter<'a>: ExactSizeIterator<Item = (&'a K, &'a V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    type IterMut<'a>: ExactSizeIterator<Item = (&'a K, &'a mut V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    fn with_hasher(state: S) -> Self
+    where
+        Self: Sized,
+        A: Default,
+    {
+        Self::with_hasher_in(state, A::default())
+    }
+
+    fn with_hasher_in(state: S, alloc: A) -> Self;
+
+    /// Create a map that can hold at least `capacity` elements without reallocating.
+    /// Maps that cannot allocate ahead of time just ignore `capacity`.
+    fn with_capacity_and_hasher(capacity: usize, state: S) -> Self
+    where
+        Self: Sized,
+        A: Default,
+    {
+        Self::with_capacity_and_hasher_in(capacity, state, A::default())
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self
+    where
+        Self: Sized,
+    {
+        let _ = capacity;
+        Self::with_hasher_in(state, alloc)
+    }
+
+    fn allocator(&self) -> &A;
+
+    fn hasher(&self) -> &S;
 
     fn len(&self) -> usize;
 
@@ -20,17 +278,150 @@ pub trait HashMap<K, V, S>: IntoIterator<Item = (K, V)> {
         K: Eq + Hash,
         S: BuildHasher;
 
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher;
+
     fn insert(&mut self, key: K, value: V) -> Option<V>
     where
         K: Eq + Hash,
         S: BuildHasher;
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher;
+
+    /// Make sure that the next `additional` insertions don't have to allocate, or return
+    /// the error of the allocator without changing the map. Maps that cannot allocate ahead
+    /// of time do nothing here, and report allocation failures from [`HashMap::try_insert`].
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher;
+
+    /// Like [`HashMap::insert`], but returns an error instead of aborting if the allocator
+    /// runs out of memory. The map is unchanged and the entry is dropped in that case.
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.try_reserve(1)?;
+        Ok(self.insert(key, value))
+    }
+
+    fn iter(&self) -> Self::Iter<'_>;
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_>;
+}
+
+/// Turns the error of a fallible allocation into the behavior of the infallible collections
+/// of `std`: abort on allocation failure, and panic on capacity overflow.
+pub(crate) fn handle_reserve<T>(result: Result<T, TryReserveError>) -> T {
+    match result.map_err(|err| err.kind()) {
+        Ok(value) => value,
+        Err(TryReserveErrorKind::AllocError { layout, .. }) => handle_alloc_error(layout),
+        Err(TryReserveErrorKind::CapacityOverflow) => panic!("capacity overflow"),
+    }
+}
+
+/// The error for a table size that doesn't fit into `usize`.
+pub(crate) fn capacity_overflow() -> TryReserveError {
+    TryReserveErrorKind::CapacityOverflow.into()
+}
+
+/// The error for an allocation of `layout` that the allocator refused.
+pub(crate) fn alloc_error(layout: Layout) -> TryReserveError {
+    TryReserveErrorKind::AllocError {
+        layout,
+        non_exhaustive: (),
+    }
+    .into()
+}
+
+/// A hash map where every modification can also create a new version of the map,
+/// leaving the old one untouched. Versions share most of their structure, so cloning is cheap.
+pub trait PersistentHashMap<K, V, S, A: Allocator = Global>: HashMap<K, V, S, A> + Clone {
+    /// A new version of the map with `key` mapped to `value`.
+    fn insert_new(&self, key: K, value: V) -> Self
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut map = self.clone();
+        map.insert(key, value);
+        map
+    }
+
+    /// A new version of the map without `key`.
+    fn remove_new(&self, key: &K) -> Self
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut map = self.clone();
+        map.remove(key);
+        map
+    }
+}
+
+pub trait ConcurrentHashMapFamily {
+    type Map<K, V, S>: ConcurrentHashMap<K, V, S>;
+}
+
+/// A hash map that can be used from many threads at once, so it only needs `&self`.
+pub trait ConcurrentHashMap<K, V, S>: IntoIterator<Item = (K, V)> {
+    /// A reference to a value in the map. The entry can't be modified while it is held.
+    type Ref<'a>: Deref<Target = V>
+    where
+        Self: 'a;
+
+    fn with_hasher(state: S) -> Self;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, key: &K) -> Option<Self::Ref<'_>>
+    where
+        K: Eq + Hash,
+        S: BuildHasher;
+
+    fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher;
+
+    fn remove(&self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher;
+
+    /// Atomically inspect and modify the entry for `key`. `f` gets `None` if the key is not
+    /// in the map and can insert it by setting it to `Some`, or remove it by setting it to `None`.
+    fn compute<R>(&self, key: K, f: impl FnOnce(&mut Option<V>) -> R) -> R
+    where
+        K: Eq + Hash,
+        S: BuildHasher;
 }
 
 #[cfg(test)]
 mod tests {
-    use std::hash::{BuildHasher, BuildHasherDefault, Hasher, RandomState};
+    use std::{
+        alloc::{AllocError, Allocator, Global, Layout},
+        cell::Cell,
+        hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hasher, RandomState},
+        ptr::NonNull,
+        rc::Rc,
+    };
 
-    use super::{HashMap, HashMapFamily};
+    use super::{
+        hash_quality::Reseed, ConcurrentHashMap, ConcurrentHashMapFamily, HashMap, HashMapFamily,
+    };
 
     #[derive(Default)]
     struct CollidingHasher;
@@ -41,11 +432,46 @@ mod tests {
         fn write(&mut self, _bytes: &[u8]) {}
     }
 
+    /// Hashes all keys to the same value with seed 0, and well with any other seed.
+    #[derive(Clone, Default)]
+    pub(super) struct WeakSeedState {
+        pub(super) seed: u64,
+        pub(super) reseed_helps: bool,
+    }
+    impl BuildHasher for WeakSeedState {
+        type Hasher = WeakSeedHasher;
+        fn build_hasher(&self) -> WeakSeedHasher {
+            let mut hasher = DefaultHasher::new();
+            hasher.write_u64(self.seed);
+            WeakSeedHasher(self.seed != 0, hasher)
+        }
+    }
+    impl Reseed for WeakSeedState {
+        fn reseed(&mut self) {
+            if self.reseed_helps {
+                self.seed += 1;
+            }
+        }
+    }
+    pub(super) struct WeakSeedHasher(bool, DefaultHasher);
+    impl Hasher for WeakSeedHasher {
+        fn finish(&self) -> u64 {
+            if self.0 {
+                self.1.finish()
+            } else {
+                0
+            }
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            self.1.write(bytes);
+        }
+    }
+
     pub(super) fn run_tests<M>()
     where
         M: HashMapFamily,
     {
-        let mk_str = || M::Map::<&str, &str, _>::with_hasher(RandomState::new());
+        let mk_str = || M::Map::<&str, &str, _, Global>::with_hasher(RandomState::new());
 
         let m = mk_str();
         assert_eq!(m.get(&"uwu"), None);
@@ -67,15 +493,109 @@ mod tests {
         m.insert("hello", "no");
         assert_eq!(m.get(&"hello"), Some(&"no"));
         assert_eq!(m.len(), 1);
+        *m.get_mut(&"hello").unwrap() = "again";
+        assert_eq!(m.get(&"hello"), Some(&"again"));
+        assert_eq!(m.get_mut(&"aaa"), None);
+
+        let mut m = mk_str();
+        assert_eq!(m.remove(&"hello"), None);
+        m.insert("hello", "world");
+        assert_eq!(m.remove(&"hello"), Some("world"));
+        assert_eq!(m.get(&"hello"), None);
+        assert_eq!(m.len(), 0);
+        assert_eq!(m.remove(&"hello"), None);
+
+        // Miri is slow, so don't make it go through huge maps.
+        let (counts, colliding): (&[usize], _) = if cfg!(miri) {
+            (&[1, 10, 100], 100)
+        } else {
+            (&[1, 10, 100, 1000, 10_000, 100_000], 1000)
+        };
 
-        for count in [1, 10, 100, 1000, 10_000, 100_000] {
+        for &count in counts {
             test_many::<M, _>(count, RandomState::new());
         }
-        test_many::<M, _>(1000, BuildHasherDefault::<CollidingHasher>::default());
+        test_many::<M, _>(colliding, BuildHasherDefault::<CollidingHasher>::default());
+
+        for capacity in [0, 1, 7, 8, 100] {
+            let mut m =
+                M::Map::<_, _, _, Global>::with_capacity_and_hasher(capacity, RandomState::new());
+            for i in 0..capacity * 2 {
+                m.insert(i, i);
+            }
+            assert_eq!(m.len(), capacity * 2);
+            assert!((0..capacity * 2).all(|i| m.get(&i) == Some(&i)));
+        }
+
+        test_remove::<M, _>(colliding, RandomState::new());
+        test_remove::<M, _>(colliding, BuildHasherDefault::<CollidingHasher>::default());
+
+        test_allocator::<M>();
+
+        super::model_check::run_model_tests::<M>();
+    }
+
+    /// An allocator that refuses to have more than `limit` bytes allocated at once.
+    #[derive(Clone)]
+    struct LimitedAlloc {
+        used: Rc<Cell<usize>>,
+        limit: Rc<Cell<usize>>,
+    }
+
+    unsafe impl Allocator for LimitedAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            let used = self.used.get() + layout.size();
+            if used > self.limit.get() {
+                return Err(AllocError);
+            }
+            let ptr = Global.allocate(layout)?;
+            self.used.set(used);
+            Ok(ptr)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.used.set(self.used.get() - layout.size());
+            // SAFETY: All memory was allocated by `Global` in `allocate`.
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    fn test_allocator<M: HashMapFamily>() {
+        let alloc = LimitedAlloc {
+            used: Rc::new(Cell::new(0)),
+            limit: Rc::new(Cell::new(usize::MAX)),
+        };
+        let mut m = M::Map::with_hasher_in(RandomState::new(), alloc.clone());
+        for i in 0..100 {
+            m.insert(i, i);
+        }
+        assert_ne!(alloc.used.get(), 0, "the map didn't use its allocator");
+
+        // Without any memory left, the map stays usable, but can't get any bigger.
+        alloc.limit.set(alloc.used.get());
+        assert!(m.try_reserve(usize::MAX).is_err());
+        let mut len = 100;
+        while let Ok(old) = m.try_insert(len, len) {
+            assert_eq!(old, None);
+            len += 1;
+        }
+        assert_eq!(m.len(), len);
+        assert!((0..len).all(|i| m.get(&i) == Some(&i)));
+        assert_eq!(m.remove(&0), Some(0));
+
+        alloc.limit.set(usize::MAX);
+        m.try_reserve(100).unwrap();
+        for i in 0..len + 100 {
+            assert_eq!(m.try_insert(i, i + 1), Ok((i != 0 && i < len).then_some(i)));
+        }
+        assert_eq!(m.len(), len + 100);
+
+        drop(m);
+        assert_eq!(alloc.used.get(), 0, "the map didn't free all of its memory");
     }
 
     fn test_many<M: HashMapFamily, H: BuildHasher>(count: usize, h: H) {
-        let mut m = M::Map::with_hasher(h);
+        let mut m = M::Map::<_, _, _, Global>::with_hasher(h);
 
         for i in 0..count {
             m.insert(i, i);
@@ -91,4 +611,123 @@ mod tests {
             assert!(found, "element {i} was lost");
         }
     }
+
+    fn test_remove<M: HashMapFamily, H: BuildHasher>(count: usize, h: H) {
+        let mut m = M::Map::<_, _, _, Global>::with_hasher(h);
+
+        // Interleave inserts and removes so that later keys have to be found
+        // past the slots of earlier, removed keys.
+        for i in 0..count {
+            m.insert(i, i);
+            if i % 3 == 2 {
+                assert_eq!(m.remove(&(i - 1)), Some(i - 1));
+            }
+        }
+        for i in 0..count {
+            let expected = (i % 3 != 1 || i == count - 1).then_some(&i);
+            assert_eq!(m.get(&i), expected, "wrong entry for {i}");
+        }
+
+        for i in (0..count).step_by(2) {
+            m.remove(&i);
+        }
+        for i in 0..count {
+            m.insert(i, i + 1);
+        }
+        assert_eq!(m.len(), count);
+
+        let mut found = vec![false; count];
+        for (k, v) in m.into_iter() {
+            assert_eq!(k + 1, v);
+            assert!(!found[k], "duplicate element");
+            found[k] = true;
+        }
+        for (i, found) in found.iter().enumerate() {
+            assert!(found, "element {i} was lost");
+        }
+    }
+
+    pub(super) fn run_concurrent_tests<M>()
+    where
+        M: ConcurrentHashMapFamily,
+        M::Map<usize, usize, RandomState>: Sync,
+    {
+        let m = M::Map::<&str, &str, _>::with_hasher(RandomState::new());
+        assert_eq!(m.get(&"uwu").as_deref(), None);
+        m.insert("hello", "world");
+        assert_eq!(m.get(&"hello").as_deref(), Some(&"world"));
+        assert_eq!(m.insert("hello", "no"), Some("world"));
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.remove(&"hello"), Some("no"));
+        assert!(m.is_empty());
+
+        test_many_threads::<M>(8, 1000);
+        test_compute::<M>(8, 1000);
+    }
+
+    fn test_many_threads<M>(threads: usize, per_thread: usize)
+    where
+        M: ConcurrentHashMapFamily,
+        M::Map<usize, usize, RandomState>: Sync,
+    {
+        let m = M::Map::with_hasher(RandomState::new());
+        let count = threads * per_thread;
+
+        std::thread::scope(|scope| {
+            for thread in 0..threads {
+                let m = &m;
+                scope.spawn(move || {
+                    for i in (thread * per_thread)..((thread + 1) * per_thread) {
+                        m.insert(i, i);
+                        assert_eq!(m.get(&i).as_deref(), Some(&i));
+                        // Remove every third element again.
+                        if i % 3 == 0 {
+                            assert_eq!(m.remove(&i), Some(i));
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(m.len(), count - count.div_ceil(3));
+        let mut found = vec![false; count];
+        for (k, v) in m.into_iter() {
+            assert_eq!(k, v);
+            assert!(!found[k], "duplicate element");
+            found[k] = true;
+        }
+        for (i, found) in found.iter().enumerate() {
+            assert_eq!(*found, i % 3 != 0, "element {i} was lost or not removed");
+        }
+    }
+
+    fn test_compute<M>(threads: usize, per_thread: usize)
+    where
+        M: ConcurrentHashMapFamily,
+        M::Map<usize, usize, RandomState>: Sync,
+    {
+        let m = M::Map::with_hasher(RandomState::new());
+        let keys = 10;
+
+        // All threads increment the same counters, none of the increments may get lost.
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                let m = &m;
+                scope.spawn(move || {
+                    for i in 0..per_thread {
+                        m.compute(i % keys, |count| {
+                            *count = Some(count.map_or(1, |count| count + 1));
+                        });
+                    }
+                });
+            }
+        });
+
+        assert_eq!(m.len(), keys);
+        for key in 0..keys {
+            assert_eq!(m.get(&key).as_deref(), Some(&(threads * per_thread / keys)));
+        }
+        m.compute(0, |count| *count = None);
+        assert_eq!(m.get(&0).as_deref(), None);
+    }
 }
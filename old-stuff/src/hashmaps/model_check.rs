@@ -0,0 +1,332 @@
+//! Randomized tests that apply the same operations to a map and to `std`'s `HashMap`,
+//! which serves as the reference model, and check that both agree after every step.
+//!
+//! When a sequence of operations fails, it is shrunk by removing as many operations as
+//! possible while it keeps failing, and the minimal sequence is reported with the seed.
+//! Set `HASHMAP_MODEL_SEED` to run only a specific seed.
+//!
+//! The tests are small enough to also run under Miri, which is what we want for the maps
+//! that use unsafe code.
+
+use std::{
+    alloc::{Allocator, Global},
+    collections::HashMap as StdHashMap,
+    hash::{BuildHasher, BuildHasherDefault, Hasher, RandomState},
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::{HashMap, HashMapFamily};
+
+/// Keys are drawn from a small range so that operations often hit existing keys.
+const KEY_RANGE: u8 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Insert(u8, u32),
+    Get(u8),
+    Remove(u8),
+    /// Iterate over the whole map and compare its contents to the model.
+    Iter,
+    /// Increment all values through a mutable iterator.
+    IncrementAll,
+}
+
+/// A xorshift generator, good enough for generating operations.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn gen_op(&mut self) -> Op {
+        let key = (self.next() % u64::from(KEY_RANGE)) as u8;
+        match self.next() % 16 {
+            0..=6 => Op::Insert(key, self.next() as u32),
+            7..=10 => Op::Get(key),
+            11..=13 => Op::Remove(key),
+            14 => Op::Iter,
+            _ => Op::IncrementAll,
+        }
+    }
+}
+
+/// Hashes all keys into just a few different values, to get long probe sequences.
+#[derive(Default)]
+struct FewHashesHasher(u64);
+impl Hasher for FewHashesHasher {
+    fn finish(&self) -> u64 {
+        self.0 % 4
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = self.0.wrapping_mul(31).wrapping_add(u64::from(byte));
+        }
+    }
+}
+
+/// Apply `ops` to a new map and to the model, returning a description of the first disagreement.
+fn check<M: HashMapFamily, H: BuildHasher>(ops: &[Op], h: &impl Fn() -> H) -> Result<(), String> {
+    let mut m = M::Map::<u8, u32, H, Global>::with_hasher(h());
+    let mut model = StdHashMap::new();
+
+    for (i, op) in ops.iter().enumerate() {
+        let (actual, expected) = match *op {
+            Op::Insert(key, value) => (m.insert(key, value), model.insert(key, value)),
+            Op::Get(key) => (m.get(&key).copied(), model.get(&key).copied()),
+            Op::Remove(key) => (m.remove(&key), model.remove(&key)),
+            Op::Iter => {
+                let iter = m.iter();
+                if iter.len() != model.len() {
+                    return Err(format!(
+                        "op {i} ({op:?}): iterator has len {}, expected {}",
+                        iter.len(),
+                        model.len()
+                    ));
+                }
+                let entries = sorted(iter.map(|(&k, &v)| (k, v)));
+                let expected = sorted(model.iter().map(|(&k, &v)| (k, v)));
+                if entries != expected {
+                    return Err(format!(
+                        "op {i} ({op:?}): iterated {entries:?}, expected {expected:?}"
+                    ));
+                }
+                (None, None)
+            }
+            Op::IncrementAll => {
+                m.iter_mut().for_each(|(_, v)| *v = v.wrapping_add(1));
+                model.values_mut().for_each(|v| *v = v.wrapping_add(1));
+                (None, None)
+            }
+        };
+        if actual != expected {
+            return Err(format!(
+                "op {i} ({op:?}): returned {actual:?}, expected {expected:?}"
+            ));
+        }
+        if m.len() != model.len() {
+            return Err(format!(
+                "op {i} ({op:?}): len is {}, expected {}",
+                m.len(),
+                model.len()
+            ));
+        }
+    }
+
+    let entries = sorted(m.into_iter());
+    let expected = sorted(model.into_iter());
+    if entries != expected {
+        return Err(format!(
+            "after all ops: consumed {entries:?}, expected {expected:?}"
+        ));
+    }
+    Ok(())
+}
+
+fn sorted(entries: impl Iterator<Item = (u8, u32)>) -> Vec<(u8, u32)> {
+    let mut entries = entries.collect::<Vec<_>>();
+    entries.sort_unstable();
+    entries
+}
+
+/// Like [`check`], but a panic in the map is also a failure.
+fn check_catching<M: HashMapFamily, H: BuildHasher>(
+    ops: &[Op],
+    h: &impl Fn() -> H,
+) -> Result<(), String> {
+    panic::catch_unwind(AssertUnwindSafe(|| check::<M, H>(ops, h))).unwrap_or_else(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(format!("panicked: {msg}"))
+    })
+}
+
+/// Find a smaller sequence of operations that still fails, by repeatedly trying to remove
+/// chunks of operations, going from large chunks to single operations.
+fn shrink<T: Clone>(mut ops: Vec<T>, fails: impl Fn(&[T]) -> bool) -> Vec<T> {
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        let mut removed_any = false;
+        while start < ops.len() {
+            let end = (start + chunk).min(ops.len());
+            let mut candidate = ops[..start].to_vec();
+            candidate.extend_from_slice(&ops[end..]);
+            if fails(&candidate) {
+                ops = candidate;
+                removed_any = true;
+            } else {
+                start += chunk;
+            }
+        }
+        if !removed_any {
+            chunk /= 2;
+        }
+    }
+    ops
+}
+
+fn run_seed<M: HashMapFamily, H: BuildHasher>(
+    seed: u64,
+    len: usize,
+    hasher: &str,
+    h: impl Fn() -> H,
+) {
+    let mut rng = Rng(seed);
+    let ops = (0..len).map(|_| rng.gen_op()).collect::<Vec<_>>();
+
+    if check_catching::<M, H>(&ops, &h).is_err() {
+        let minimal = shrink(ops, |ops| check_catching::<M, H>(ops, &h).is_err());
+        let err = check_catching::<M, H>(&minimal, &h).unwrap_err();
+        panic!(
+            "model check failed with seed {seed} and {hasher} hasher: {err}\n\
+            minimal sequence of operations: {minimal:#?}"
+        );
+    }
+}
+
+pub(super) fn run_model_tests<M: HashMapFamily>() {
+    let (seeds, len) = if cfg!(miri) { (2, 100) } else { (50, 1000) };
+    let seeds = match std::env::var("HASHMAP_MODEL_SEED") {
+        Ok(seed) => vec![seed.parse().expect("HASHMAP_MODEL_SEED must be a number")],
+        Err(_) => (1..=seeds)
+            .map(|i: u64| i.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            .collect(),
+    };
+
+    for seed in seeds {
+        run_seed::<M, _>(seed, len, "random", RandomState::new);
+        run_seed::<M, _>(
+            seed,
+            len,
+            "few hashes",
+            BuildHasherDefault::<FewHashesHasher>::default,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shrinks_to_minimal_sequence() {
+        let ops = (0..100)
+            .map(|i| match i {
+                17 => Op::Insert(3, 1),
+                60 => Op::Remove(3),
+                _ => Op::Get(i as u8),
+            })
+            .collect::<Vec<_>>();
+        let fails = |ops: &[Op]| {
+            let insert = ops.iter().position(|op| *op == Op::Insert(3, 1));
+            let remove = ops.iter().rposition(|op| *op == Op::Remove(3));
+            matches!((insert, remove), (Some(insert), Some(remove)) if insert < remove)
+        };
+
+        assert_eq!(shrink(ops, fails), [Op::Insert(3, 1), Op::Remove(3)]);
+    }
+
+    #[test]
+    fn finds_broken_map() {
+        use crate::hashmaps::simple_open_addressing::SimpleOAHashMap;
+        use std::collections::TryReserveError;
+
+        /// A map that forgets to remove entries.
+        struct Forgetful<K, V, S, A: Allocator>(SimpleOAHashMap<K, V, S, A>);
+
+        impl<K, V, S, A: Allocator> IntoIterator for Forgetful<K, V, S, A> {
+            type Item = (K, V);
+            type IntoIter = <SimpleOAHashMap<K, V, S, A> as IntoIterator>::IntoIter;
+            fn into_iter(self) -> Self::IntoIter {
+                self.0.into_iter()
+            }
+        }
+
+        impl<K, V, S, A: Allocator + Clone> HashMap<K, V, S, A> for Forgetful<K, V, S, A> {
+            type Iter<'a>
+                = <SimpleOAHashMap<K, V, S, A> as HashMap<K, V, S, A>>::Iter<'a>
+            where
+                Self: 'a;
+            type IterMut<'a>
+                = <SimpleOAHashMap<K, V, S, A> as HashMap<K, V, S, A>>::IterMut<'a>
+            where
+                Self: 'a;
+            fn with_hasher_in(state: S, alloc: A) -> Self {
+                Self(HashMap::with_hasher_in(state, alloc))
+            }
+            fn allocator(&self) -> &A {
+                self.0.allocator()
+            }
+            fn hasher(&self) -> &S {
+                self.0.hasher()
+            }
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+            fn get(&self, key: &K) -> Option<&V>
+            where
+                K: Eq + std::hash::Hash,
+                S: BuildHasher,
+            {
+                self.0.get(key)
+            }
+            fn get_mut(&mut self, key: &K) -> Option<&mut V>
+            where
+                K: Eq + std::hash::Hash,
+                S: BuildHasher,
+            {
+                self.0.get_mut(key)
+            }
+            fn insert(&mut self, key: K, value: V) -> Option<V>
+            where
+                K: Eq + std::hash::Hash,
+                S: BuildHasher,
+            {
+                self.0.insert(key, value)
+            }
+            fn remove(&mut self, _key: &K) -> Option<V>
+            where
+                K: Eq + std::hash::Hash,
+                S: BuildHasher,
+            {
+                None
+            }
+            fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+            where
+                K: Eq + std::hash::Hash,
+                S: BuildHasher,
+            {
+                self.0.try_reserve(additional)
+            }
+            fn iter(&self) -> Self::Iter<'_> {
+                self.0.iter()
+            }
+            fn iter_mut(&mut self) -> Self::IterMut<'_> {
+                self.0.iter_mut()
+            }
+        }
+
+        struct ForgetfulFamily;
+        impl HashMapFamily for ForgetfulFamily {
+            type Map<K, V, S, A: Allocator + Clone> = Forgetful<K, V, S, A>;
+        }
+
+        let mut rng = Rng(1);
+        let ops = (0..1000).map(|_| rng.gen_op()).collect::<Vec<_>>();
+        let h = RandomState::new;
+        assert!(check::<ForgetfulFamily, _>(&ops, &h).is_err());
+
+        let minimal = shrink(ops, |ops| check::<ForgetfulFamily, _>(ops, &h).is_err());
+        assert!(
+            matches!(minimal[..], [Op::Insert(a, _), Op::Remove(b)] if a == b),
+            "{minimal:?}"
+        );
+    }
+}
@@ -0,0 +1,527 @@
+//! An open addressing hash map with Robin Hood hashing.
+//!
+//! Every entry knows its probe sequence length (PSL), the distance from its home bucket.
+//! When inserting, an entry that has travelled further than the one occupying a bucket
+//! takes the bucket ("steals from the rich") and the displaced entry continues probing.
+//! This keeps probe sequence lengths close to each other, and allows lookups to stop as soon
+//! as they see an entry that is closer to its home than the key would be.
+//!
+//! Removal uses backward-shift deletion: the entries after the removed one are moved back
+//! by one bucket until one is found that is empty or already in its home bucket, so no
+//! tombstones are needed.
+//!
+//! Robin Hood hashing keeps probe sequences short for a good hash function, but not for keys
+//! that collide, so the map offers the same HashDoS protection as the
+//! [simple open addressing map](super::simple_open_addressing).
+
+use super::{
+    capacity_overflow, handle_reserve,
+    hash_quality::{HashDosProtection, HashStats, Reseed},
+    HashMap, HashMapFamily,
+};
+use std::{
+    alloc::{Allocator, Global},
+    collections::TryReserveError,
+    hash::{BuildHasher, Hash, RandomState},
+    vec,
+};
+
+/// The number of buckets of the first allocation.
+const MIN_BUCKETS: usize = 8;
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    hash: u64,
+    /// The probe sequence length, the distance from the home bucket.
+    psl: usize,
+}
+
+/// The number of buckets needed to hold `capacity` entries at a load factor of at most 7/8.
+fn buckets_for(capacity: usize) -> Result<usize, TryReserveError> {
+    if capacity == 0 {
+        return Ok(0);
+    }
+    let buckets = capacity
+        .checked_mul(8)
+        .map(|buckets| buckets.div_ceil(7))
+        .and_then(usize::checked_next_power_of_two)
+        .ok_or_else(capacity_overflow)?;
+    Ok(buckets.max(MIN_BUCKETS))
+}
+
+/// A table of `len` empty buckets, allocated with `alloc`.
+fn try_empty_buckets<K, V, A: Allocator>(
+    len: usize,
+    alloc: A,
+) -> Result<Vec<Option<Entry<K, V>>, A>, TryReserveError> {
+    let mut buckets = Vec::new_in(alloc);
+    buckets.try_reserve_exact(len)?;
+    buckets.resize_with(len, || None);
+    Ok(buckets)
+}
+
+pub struct RobinHoodHashMap<K, V, S = RandomState, A: Allocator = Global> {
+    buckets: Vec<Option<Entry<K, V>>, A>,
+    filled: usize,
+    protection: HashDosProtection<S>,
+    s: S,
+}
+
+impl<K: Eq + Hash, V> RobinHoodHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> RobinHoodHashMap<K, V, S> {
+    pub fn with_hasher(state: S) -> Self {
+        HashMap::with_hasher_in(state, Global)
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> RobinHoodHashMap<K, V, S, A> {
+    /// The longest probe sequence of any entry in the map. A probe length of 0 means that
+    /// all entries are in their home bucket.
+    pub fn max_probe_len(&self) -> usize {
+        self.buckets
+            .iter()
+            .flatten()
+            .map(|entry| entry.psl)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// How well the keys are distributed over the table.
+    pub fn stats(&self) -> HashStats {
+        let probe_lens = self.buckets.iter().flatten().map(|entry| entry.psl);
+        HashStats::new(self.buckets.iter().map(Option::is_some), probe_lens)
+    }
+
+    /// Switch to a new hash seed and rehash whenever an insertion has to move an entry much
+    /// further from its home bucket than it would with a good hash function.
+    ///
+    /// If the keys still collide with the new seed, the map stops reseeding.
+    pub fn enable_hashdos_protection(&mut self)
+    where
+        S: Reseed,
+    {
+        self.protection.enable();
+    }
+
+    fn mask(&self) -> usize {
+        self.buckets.len() - 1
+    }
+
+    fn home(&self, hash: u64) -> usize {
+        hash as usize & self.mask()
+    }
+
+    /// Place an entry that is known not to be in the map yet, displacing richer entries.
+    /// There must be at least one empty bucket. Returns the longest probe sequence length of
+    /// the entries it placed.
+    fn insert_new(&mut self, mut entry: Entry<K, V>) -> usize {
+        let mut pos = self.home(entry.hash);
+        entry.psl = 0;
+        let mut max_psl = 0;
+        loop {
+            max_psl = max_psl.max(entry.psl);
+            match &mut self.buckets[pos] {
+                slot @ None => {
+                    *slot = Some(entry);
+                    self.filled += 1;
+                    return max_psl;
+                }
+                Some(existing) => {
+                    if existing.psl < entry.psl {
+                        std::mem::swap(existing, &mut entry);
+                    }
+                }
+            }
+            entry.psl += 1;
+            pos = (pos + 1) & self.mask();
+        }
+    }
+
+    fn grow(&mut self) {
+        let len = self.buckets.len();
+        let new = if len == 0 { MIN_BUCKETS } else { len * 2 };
+        handle_reserve(self.try_resize(new));
+    }
+
+    /// Move all entries into a new table of `new` buckets.
+    fn try_resize(&mut self, new: usize) -> Result<(), TryReserveError> {
+        let buckets = try_empty_buckets(new, self.buckets.allocator().clone())?;
+        let old = std::mem::replace(&mut self.buckets, buckets);
+        self.filled = 0;
+        for entry in old.into_iter().flatten() {
+            self.insert_new(entry);
+        }
+        Ok(())
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, A: Allocator + Clone> RobinHoodHashMap<K, V, S, A> {
+    /// The index of the bucket containing `key`, if there is one.
+    fn find(&self, key: &K) -> Option<usize> {
+        if self.filled == 0 {
+            return None;
+        }
+        let hash = self.s.hash_one(key);
+        let mut pos = self.home(hash);
+        for dist in 0..self.buckets.len() {
+            match &self.buckets[pos] {
+                // If the key were here, it would have stolen this bucket.
+                Some(entry) if entry.psl < dist => return None,
+                Some(entry) if entry.hash == hash && entry.key == *key => return Some(pos),
+                Some(_) => {}
+                None => return None,
+            }
+            pos = (pos + 1) & self.mask();
+        }
+        None
+    }
+
+    fn reseed_and_rehash(&mut self) {
+        // Allocate before reseeding, as the entries can't be found with the new seed until
+        // they are rehashed. Without the memory, the map just stays slow.
+        let alloc = self.buckets.allocator().clone();
+        let Ok(buckets) = try_empty_buckets(self.buckets.len(), alloc) else {
+            return;
+        };
+        self.protection.reseed(&mut self.s);
+        let old = std::mem::replace(&mut self.buckets, buckets);
+        self.filled = 0;
+        for mut entry in old.into_iter().flatten() {
+            entry.hash = self.s.hash_one(&entry.key);
+            self.insert_new(entry);
+        }
+        let stats = self.stats();
+        self.protection.check_reseed(&stats);
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> HashMap<K, V, S, A> for RobinHoodHashMap<K, V, S, A> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            buckets: Vec::new_in(alloc),
+            filled: 0,
+            protection: HashDosProtection::disabled(),
+            s: state,
+        }
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        let buckets = handle_reserve(buckets_for(capacity));
+        Self {
+            buckets: handle_reserve(try_empty_buckets(buckets, alloc)),
+            filled: 0,
+            protection: HashDosProtection::disabled(),
+            s: state,
+        }
+    }
+
+    fn allocator(&self) -> &A {
+        self.buckets.allocator()
+    }
+
+    fn hasher(&self) -> &S {
+        &self.s
+    }
+
+    fn len(&self) -> usize {
+        self.filled
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let pos = self.find(key)?;
+        self.buckets[pos].as_ref().map(|entry| &entry.value)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let pos = self.find(key)?;
+        self.buckets[pos].as_mut().map(|entry| &mut entry.value)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if let Some(pos) = self.find(&key) {
+            let entry = self.buckets[pos].as_mut().unwrap();
+            return Some(std::mem::replace(&mut entry.value, value));
+        }
+
+        // Keep the load factor at or below 7/8.
+        if (self.filled + 1) * 8 > self.buckets.len() * 7 {
+            self.grow();
+        }
+        let hash = self.s.hash_one(&key);
+        let psl = self.insert_new(Entry {
+            key,
+            value,
+            hash,
+            psl: 0,
+        });
+        if self.protection.is_triggered(psl, self.buckets.len()) {
+            self.reseed_and_rehash();
+        }
+        None
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut pos = self.find(key)?;
+        let removed = self.buckets[pos].take().unwrap();
+        self.filled -= 1;
+
+        // Shift back all following entries that are not in their home bucket.
+        loop {
+            let next = (pos + 1) & self.mask();
+            match self.buckets[next].take() {
+                Some(mut entry) if entry.psl > 0 => {
+                    entry.psl -= 1;
+                    self.buckets[pos] = Some(entry);
+                    pos = next;
+                }
+                entry => {
+                    self.buckets[next] = entry;
+                    break;
+                }
+            }
+        }
+
+        Some(removed.value)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let required = self
+            .filled
+            .checked_add(additional)
+            .ok_or_else(capacity_overflow)?;
+        let new = buckets_for(required)?;
+        if new > self.buckets.len() {
+            self.try_resize(new)?;
+        }
+        Ok(())
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            buckets: self.buckets.iter(),
+            remaining: self.filled,
+        }
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        IterMut {
+            buckets: self.buckets.iter_mut(),
+            remaining: self.filled,
+        }
+    }
+}
+
+impl_std_traits!(RobinHoodHashMap);
+
+pub struct Iter<'a, K, V> {
+    buckets: std::slice::Iter<'a, Option<Entry<K, V>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.find_map(|bucket| {
+            let entry = bucket.as_ref()?;
+            Some((&entry.key, &entry.value))
+        })?;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub struct IterMut<'a, K, V> {
+    buckets: std::slice::IterMut<'a, Option<Entry<K, V>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.find_map(|bucket| {
+            let entry = bucket.as_mut()?;
+            Some((&entry.key, &mut entry.value))
+        })?;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
+type IntoIterInner<K, V, A> = std::iter::FilterMap<
+    vec::IntoIter<Option<Entry<K, V>>, A>,
+    fn(Option<Entry<K, V>>) -> Option<(K, V)>,
+>;
+
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    buckets: IntoIterInner<K, V, A>,
+}
+
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buckets.next()
+    }
+}
+
+impl<K, V, S, A: Allocator> IntoIterator for RobinHoodHashMap<K, V, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            buckets: self
+                .buckets
+                .into_iter()
+                .filter_map(|entry| entry.map(|entry| (entry.key, entry.value))),
+        }
+    }
+}
+
+pub struct RobinHoodHashMapFamily;
+impl HashMapFamily for RobinHoodHashMapFamily {
+    type Map<K, V, S, A: Allocator + Clone> = RobinHoodHashMap<K, V, S, A>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    use super::RobinHoodHashMap;
+    use crate::hashmaps::{tests::WeakSeedState, HashMap};
+
+    #[test]
+    fn do_tests() {
+        crate::hashmaps::tests::run_tests::<super::RobinHoodHashMapFamily>();
+    }
+
+    #[derive(Default)]
+    struct IdentityHasher(u64);
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+        fn write(&mut self, _bytes: &[u8]) {
+            unreachable!("only write_u64 is used")
+        }
+        fn write_u64(&mut self, i: u64) {
+            self.0 = i;
+        }
+    }
+
+    #[test]
+    fn steals_from_the_rich() {
+        let mut m = RobinHoodHashMap::with_hasher(BuildHasherDefault::<IdentityHasher>::default());
+        // 0 and 8 both want bucket 0, 1 wants bucket 1.
+        m.insert(1u64, ());
+        m.insert(0, ());
+        assert_eq!(m.max_probe_len(), 0);
+        m.insert(8, ());
+        // 8 takes bucket 1 from 1, instead of being pushed further itself.
+        assert_eq!(m.max_probe_len(), 1);
+        assert_eq!(m.buckets[1].as_ref().unwrap().key, 8);
+        assert_eq!(m.buckets[2].as_ref().unwrap().key, 1);
+
+        // Removing 0 shifts both back into their home buckets.
+        m.remove(&0);
+        assert_eq!(m.max_probe_len(), 0);
+        assert_eq!(m.stats().probe_lengths, [2]);
+        assert_eq!(m.buckets[0].as_ref().unwrap().key, 8);
+        assert_eq!(m.buckets[1].as_ref().unwrap().key, 1);
+        assert!(m.buckets[2].is_none());
+    }
+
+    #[test]
+    fn probe_len_of_colliding_keys() {
+        #[derive(Default)]
+        struct CollidingHasher;
+        impl Hasher for CollidingHasher {
+            fn finish(&self) -> u64 {
+                0
+            }
+            fn write(&mut self, _bytes: &[u8]) {}
+        }
+
+        let mut m = RobinHoodHashMap::with_hasher(BuildHasherDefault::<CollidingHasher>::default());
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.max_probe_len(), 999);
+    }
+
+    #[test]
+    fn hashdos_protection() {
+        let state = WeakSeedState {
+            seed: 0,
+            reseed_helps: true,
+        };
+        let mut m = RobinHoodHashMap::with_hasher(state);
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.hasher().seed, 1);
+        assert!(!m.stats().is_degenerate(), "{}", m.stats());
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+
+        let mut m = RobinHoodHashMap::with_hasher(WeakSeedState::default());
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert!(!m.protection.is_enabled());
+        assert_eq!(m.max_probe_len(), 999);
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+    }
+}
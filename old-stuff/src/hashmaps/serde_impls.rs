@@ -0,0 +1,254 @@
+//! `Serialize` and `Deserialize` for all maps, enabled by the `serde` feature.
+//!
+//! Maps are serialized as serde maps. Input with the same key twice was most likely not
+//! produced by serializing one of our maps, so `Deserialize` rejects it. [`MapSeed`] can
+//! deserialize with a different [`DuplicateKeys`] policy or a specific hasher.
+
+use std::{fmt, marker::PhantomData};
+
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+
+/// Never allocate more than this ahead of time, as the length hint comes from the input,
+/// which might be lying.
+const MAX_PREALLOCATION_BYTES: usize = 1024 * 1024;
+
+/// What to do when the input contains a key more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Fail deserialization.
+    #[default]
+    Error,
+    /// Keep the value that came first and skip the others.
+    KeepFirst,
+    /// Keep the value that came last, like inserting all entries one after another.
+    KeepLast,
+}
+
+/// A map that can be built by [`MapSeed`]. All maps implement this.
+pub trait DeserializeMap<K, V, S>: Sized {
+    fn with_capacity_and_hasher(capacity: usize, state: S) -> Self;
+
+    fn contains_key(&self, key: &K) -> bool;
+
+    fn insert(&mut self, key: K, value: V);
+}
+
+/// Deserializes a map of type `M` with the given hasher and duplicate key policy.
+///
+/// ```ignore
+/// let map: SimpleOAHashMap<String, u32> =
+///     MapSeed::new(DuplicateKeys::KeepLast).deserialize(&mut deserializer)?;
+/// ```
+pub struct MapSeed<M, K, V, S> {
+    state: S,
+    duplicates: DuplicateKeys,
+    _map: PhantomData<fn(K, V) -> M>,
+}
+
+impl<M, K, V, S: Default> MapSeed<M, K, V, S> {
+    pub fn new(duplicates: DuplicateKeys) -> Self {
+        Self::with_hasher(S::default(), duplicates)
+    }
+}
+
+impl<M, K, V, S> MapSeed<M, K, V, S> {
+    pub fn with_hasher(state: S, duplicates: DuplicateKeys) -> Self {
+        Self {
+            state,
+            duplicates,
+            _map: PhantomData,
+        }
+    }
+}
+
+impl<'de, M, K, V, S> DeserializeSeed<'de> for MapSeed<M, K, V, S>
+where
+    M: DeserializeMap<K, V, S>,
+    K: de::Deserialize<'de>,
+    V: de::Deserialize<'de>,
+{
+    type Value = M;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<M, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, M, K, V, S> Visitor<'de> for MapSeed<M, K, V, S>
+where
+    M: DeserializeMap<K, V, S>,
+    K: de::Deserialize<'de>,
+    V: de::Deserialize<'de>,
+{
+    type Value = M;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<M, A::Error> {
+        let max_capacity = MAX_PREALLOCATION_BYTES / std::mem::size_of::<(K, V)>().max(1);
+        let capacity = access.size_hint().unwrap_or(0).min(max_capacity);
+        let mut map = M::with_capacity_and_hasher(capacity, self.state);
+
+        let mut entry = 0;
+        while let Some(key) = access.next_key::<K>()? {
+            if map.contains_key(&key) {
+                match self.duplicates {
+                    DuplicateKeys::Error => {
+                        return Err(de::Error::custom(format_args!(
+                            "duplicate key in map at entry {entry}"
+                        )));
+                    }
+                    DuplicateKeys::KeepFirst => {
+                        access.next_value::<IgnoredAny>()?;
+                        entry += 1;
+                        continue;
+                    }
+                    DuplicateKeys::KeepLast => {}
+                }
+            }
+            let value = access.next_value()?;
+            map.insert(key, value);
+            entry += 1;
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasherDefault, DefaultHasher};
+
+    use serde::de::{value, DeserializeSeed};
+    use serde_test::{assert_de_tokens_error, assert_ser_tokens, assert_tokens, Token};
+
+    use super::{DeserializeMap, DuplicateKeys, MapSeed};
+    use crate::hashmaps::{
+        chained::ChainedHashMap, cuckoo::CuckooHashMap, hamt::HamtHashMap, indexed::IndexedHashMap,
+        robin_hood::RobinHoodHashMap, sharded::ShardedHashMap,
+        simple_open_addressing::SimpleOAHashMap, small::SmallHashMap, swiss_table::SwissHashMap,
+        ConcurrentHashMap, HashMap,
+    };
+
+    type Fixed = BuildHasherDefault<DefaultHasher>;
+
+    fn round_trip<M>()
+    where
+        M: HashMap<u8, char, Fixed>
+            + serde::Serialize
+            + for<'de> serde::Deserialize<'de>
+            + PartialEq
+            + std::fmt::Debug,
+    {
+        // A single entry, so that the order of the tokens doesn't depend on the map.
+        let mut map = M::with_hasher(Fixed::default());
+        map.insert(1, 'a');
+        assert_tokens(
+            &map,
+            &[
+                Token::Map { len: Some(1) },
+                Token::U8(1),
+                Token::Char('a'),
+                Token::MapEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn all_maps_round_trip() {
+        round_trip::<SimpleOAHashMap<_, _, _>>();
+        round_trip::<SwissHashMap<_, _, _>>();
+        round_trip::<RobinHoodHashMap<_, _, _>>();
+        round_trip::<ChainedHashMap<_, _, _>>();
+        round_trip::<CuckooHashMap<_, _, _>>();
+        round_trip::<HamtHashMap<_, _, _>>();
+        round_trip::<IndexedHashMap<_, _, _>>();
+        // Once inline, and once spilled.
+        round_trip::<SmallHashMap<_, _, 8, _>>();
+        round_trip::<SmallHashMap<_, _, 0, _>>();
+    }
+
+    /// Deserializes `entries`, pretending that there are `len_hint` of them.
+    fn deserialize<M>(
+        seed: MapSeed<M, u8, char, Fixed>,
+        entries: &[(u8, char)],
+        len_hint: usize,
+    ) -> Result<M, value::Error>
+    where
+        M: DeserializeMap<u8, char, Fixed>,
+    {
+        struct Hinted<I>(I, usize);
+        impl<I: Iterator> Iterator for Hinted<I> {
+            type Item = I::Item;
+            fn next(&mut self) -> Option<I::Item> {
+                self.0.next()
+            }
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                (self.1, Some(self.1))
+            }
+        }
+
+        let entries = Hinted(entries.iter().copied(), len_hint);
+        seed.deserialize(value::MapDeserializer::new(entries))
+    }
+
+    #[test]
+    fn sharded_round_trip() {
+        let map = ShardedHashMap::<u8, char, Fixed>::default();
+        map.insert(1, 'a');
+        assert_ser_tokens(
+            &map,
+            &[
+                Token::Map { len: Some(1) },
+                Token::U8(1),
+                Token::Char('a'),
+                Token::MapEnd,
+            ],
+        );
+
+        let seed = MapSeed::new(DuplicateKeys::Error);
+        let map: ShardedHashMap<u8, char, Fixed> = deserialize(seed, &[(1, 'a')], 1).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&1).as_deref(), Some(&'a'));
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        assert_de_tokens_error::<SimpleOAHashMap<u8, char, Fixed>>(
+            &[
+                Token::Map { len: Some(3) },
+                Token::U8(1),
+                Token::Char('a'),
+                Token::U8(2),
+                Token::Char('b'),
+                Token::U8(1),
+            ],
+            "duplicate key in map at entry 2",
+        );
+
+        let entries = [(1, 'a'), (2, 'b'), (1, 'c')];
+        let first: SimpleOAHashMap<u8, char, Fixed> =
+            deserialize(MapSeed::new(DuplicateKeys::KeepFirst), &entries, 3).unwrap();
+        assert_eq!((first.len(), first[&1], first[&2]), (2, 'a', 'b'));
+        let last: SimpleOAHashMap<u8, char, Fixed> =
+            deserialize(MapSeed::new(DuplicateKeys::KeepLast), &entries, 3).unwrap();
+        assert_eq!((last.len(), last[&1], last[&2]), (2, 'c', 'b'));
+    }
+
+    #[test]
+    fn presizes_from_length_hint() {
+        let seed = MapSeed::new(DuplicateKeys::Error);
+        let map: SimpleOAHashMap<u8, char, Fixed> = deserialize(seed, &[(1, 'a')], 100).unwrap();
+        assert!(map.capacity() >= 100, "capacity was {}", map.capacity());
+
+        // Don't trust huge length hints.
+        let seed = MapSeed::new(DuplicateKeys::Error);
+        let map: SimpleOAHashMap<u8, char, Fixed> = deserialize(seed, &[], usize::MAX).unwrap();
+        assert!(
+            map.capacity() <= 1024 * 1024,
+            "capacity was {}",
+            map.capacity()
+        );
+    }
+}
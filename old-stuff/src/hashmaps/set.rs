@@ -0,0 +1,357 @@
+//! Hash sets, which are just hash maps with `()` values. Every [`HashMap`] is a [`HashSet`],
+//! and every [`HashMapFamily`] is a [`HashSetFamily`].
+
+use std::{
+    alloc::{Allocator, Global},
+    hash::{BuildHasher, Hash},
+    iter::Chain,
+    marker::PhantomData,
+};
+
+use super::{HashMap, HashMapFamily};
+
+pub trait HashSetFamily {
+    type Set<T, S, A: Allocator + Clone>: HashSet<T, S, A>;
+}
+
+impl<F: HashMapFamily> HashSetFamily for F {
+    type Set<T, S, A: Allocator + Clone> = F::Map<T, (), S, A>;
+}
+
+pub trait HashSet<T, S, A: Allocator = Global> {
+    type Iter<'a>: ExactSizeIterator<Item = &'a T>
+    where
+        Self: 'a,
+        T: 'a;
+
+    fn with_hasher(state: S) -> Self
+    where
+        Self: Sized,
+        A: Default,
+    {
+        Self::with_hasher_in(state, A::default())
+    }
+
+    fn with_hasher_in(state: S, alloc: A) -> Self;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn contains(&self, value: &T) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher;
+
+    /// Returns whether the value was newly inserted.
+    fn insert(&mut self, value: T) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher;
+
+    /// Returns whether the value was present.
+    fn remove(&mut self, value: &T) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher;
+
+    fn iter(&self) -> Self::Iter<'_>;
+
+    /// The values that are in `self` or `other`, without duplicates.
+    fn union<'a>(&'a self, other: &'a Self) -> Union<'a, T, S, Self, A>
+    where
+        Self: Sized,
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        Union {
+            iter: self.iter().chain(other.difference(self)),
+        }
+    }
+
+    /// The values that are in both `self` and `other`.
+    fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, T, S, Self, A>
+    where
+        Self: Sized,
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        Intersection {
+            iter: self.iter(),
+            other,
+            _s: PhantomData,
+        }
+    }
+
+    /// The values that are in `self` but not in `other`.
+    fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, T, S, Self, A>
+    where
+        Self: Sized,
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        Difference {
+            iter: self.iter(),
+            other,
+            _s: PhantomData,
+        }
+    }
+
+    /// The values that are in either `self` or `other`, but not in both.
+    fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, T, S, Self, A>
+    where
+        Self: Sized,
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        SymmetricDifference {
+            iter: self.difference(other).chain(other.difference(self)),
+        }
+    }
+
+    /// Whether all values of `self` are also in `other`.
+    fn is_subset(&self, other: &Self) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.len() <= other.len() && self.iter().all(|value| other.contains(value))
+    }
+
+    /// Whether all values of `other` are also in `self`.
+    fn is_superset(&self, other: &Self) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        other.is_subset(self)
+    }
+
+    /// Whether `self` and `other` have no values in common.
+    fn is_disjoint(&self, other: &Self) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        let (smaller, larger) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        !smaller.iter().any(|value| larger.contains(value))
+    }
+}
+
+impl<T, S, A: Allocator, M: HashMap<T, (), S, A>> HashSet<T, S, A> for M {
+    type Iter<'a>
+        = Iter<M::Iter<'a>>
+    where
+        Self: 'a,
+        T: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        HashMap::with_hasher_in(state, alloc)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn contains(&self, value: &T) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        HashMap::get(self, value).is_some()
+    }
+
+    fn insert(&mut self, value: T) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        HashMap::insert(self, value, ()).is_none()
+    }
+
+    fn remove(&mut self, value: &T) -> bool
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        HashMap::remove(self, value).is_some()
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            iter: HashMap::iter(self),
+        }
+    }
+}
+
+/// The values of a set, which are the keys of the underlying map.
+pub struct Iter<I> {
+    iter: I,
+}
+
+impl<'a, T: 'a, I: Iterator<Item = (&'a T, &'a ())>> Iterator for Iter<I> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(value, ())| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T: 'a, I: ExactSizeIterator<Item = (&'a T, &'a ())>> ExactSizeIterator for Iter<I> {}
+
+pub struct Union<'a, T: 'a, S, M: HashSet<T, S, A> + 'a, A: Allocator = Global> {
+    iter: Chain<M::Iter<'a>, Difference<'a, T, S, M, A>>,
+}
+
+impl<'a, T, S, M, A> Iterator for Union<'a, T, S, M, A>
+where
+    T: Eq + Hash + 'a,
+    S: BuildHasher,
+    M: HashSet<T, S, A> + 'a,
+    A: Allocator,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+pub struct Intersection<'a, T: 'a, S, M: HashSet<T, S, A> + 'a, A: Allocator = Global> {
+    iter: M::Iter<'a>,
+    other: &'a M,
+    _s: PhantomData<fn() -> (S, A)>,
+}
+
+impl<'a, T, S, M, A> Iterator for Intersection<'a, T, S, M, A>
+where
+    T: Eq + Hash + 'a,
+    S: BuildHasher,
+    M: HashSet<T, S, A> + 'a,
+    A: Allocator,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find(|value| self.other.contains(value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+pub struct Difference<'a, T: 'a, S, M: HashSet<T, S, A> + 'a, A: Allocator = Global> {
+    iter: M::Iter<'a>,
+    other: &'a M,
+    _s: PhantomData<fn() -> (S, A)>,
+}
+
+impl<'a, T, S, M, A> Iterator for Difference<'a, T, S, M, A>
+where
+    T: Eq + Hash + 'a,
+    S: BuildHasher,
+    M: HashSet<T, S, A> + 'a,
+    A: Allocator,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find(|value| !self.other.contains(value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+type SymmetricDifferenceInner<'a, T, S, M, A> =
+    Chain<Difference<'a, T, S, M, A>, Difference<'a, T, S, M, A>>;
+
+pub struct SymmetricDifference<'a, T: 'a, S, M: HashSet<T, S, A> + 'a, A: Allocator = Global> {
+    iter: SymmetricDifferenceInner<'a, T, S, M, A>,
+}
+
+impl<'a, T, S, M, A> Iterator for SymmetricDifference<'a, T, S, M, A>
+where
+    T: Eq + Hash + 'a,
+    S: BuildHasher,
+    M: HashSet<T, S, A> + 'a,
+    A: Allocator,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::Global, hash::RandomState};
+
+    use super::{HashSet, HashSetFamily};
+    use crate::hashmaps::{
+        simple_open_addressing::SimpleOAHashMapFamily, swiss_table::SwissHashMapFamily,
+    };
+
+    fn run_set_tests<F: HashSetFamily>() {
+        let state = RandomState::new();
+        let set = |values: &[u32]| {
+            let mut set = F::Set::<_, _, Global>::with_hasher(state.clone());
+            for &value in values {
+                set.insert(value);
+            }
+            set
+        };
+        let sorted = |iter: &mut dyn Iterator<Item = &u32>| {
+            let mut values = iter.copied().collect::<Vec<_>>();
+            values.sort_unstable();
+            values
+        };
+
+        let mut s = set(&[]);
+        assert!(s.insert(1));
+        assert!(!s.insert(1));
+        assert!(s.contains(&1));
+        assert_eq!(s.iter().len(), 1);
+        assert!(s.remove(&1));
+        assert!(!s.remove(&1));
+        assert!(s.is_empty());
+
+        let a = set(&[1, 2, 3, 4]);
+        let b = set(&[3, 4, 5]);
+        assert_eq!(sorted(&mut a.union(&b)), [1, 2, 3, 4, 5]);
+        assert_eq!(sorted(&mut a.intersection(&b)), [3, 4]);
+        assert_eq!(sorted(&mut a.difference(&b)), [1, 2]);
+        assert_eq!(sorted(&mut b.difference(&a)), [5]);
+        assert_eq!(sorted(&mut a.symmetric_difference(&b)), [1, 2, 5]);
+
+        assert!(set(&[3, 4]).is_subset(&a));
+        assert!(!b.is_subset(&a));
+        assert!(a.is_superset(&set(&[1, 4])));
+        assert!(set(&[]).is_subset(&a));
+        assert!(a.is_disjoint(&set(&[7, 8])));
+        assert!(!a.is_disjoint(&b));
+    }
+
+    #[test]
+    fn simple_oa_set() {
+        run_set_tests::<SimpleOAHashMapFamily>();
+    }
+
+    #[test]
+    fn swiss_set() {
+        run_set_tests::<SwissHashMapFamily>();
+    }
+}
@@ -0,0 +1,385 @@
+//! A concurrent hash map made of many independently locked shards.
+//!
+//! Every shard is a normal [`HashMap`] from some [`HashMapFamily`] behind an [`RwLock`].
+//! The shard of a key is chosen by the upper bits of its hash, so threads working on different
+//! keys rarely contend on the same lock. The shards hash with the lower bits, so the shard
+//! maps still see evenly distributed hashes.
+//!
+//! The shards and everything they allocate live in the allocator `A` of the sharded map.
+//!
+//! The shards store `Option<V>`, so that `compute` can hand out the value in place. Outside of
+//! `compute`, a shard never holds `None`.
+//!
+//! A panic in the closure passed to `compute` leaves the entry as the closure left it, and
+//! doesn't poison the shard. Any other panic while a shard is locked, like from `Hash`, `Eq` or
+//! the allocator in the middle of growing the shard, may leave the shard inconsistent, so later
+//! accesses to that shard panic as well.
+
+use super::{
+    simple_open_addressing::SimpleOAHashMapFamily, ConcurrentHashMap, ConcurrentHashMapFamily,
+    HashMap, HashMapFamily,
+};
+use std::{
+    alloc::{Allocator, Global},
+    hash::{BuildHasher, Hash, RandomState},
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, MappedRwLockReadGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    vec,
+};
+
+/// The number of shards if not specified otherwise.
+const DEFAULT_SHARDS: usize = 16;
+
+/// The hasher of the shards, which all share the hasher of the sharded map.
+pub struct SharedState<S>(Arc<S>);
+
+impl<S: BuildHasher> BuildHasher for SharedState<S> {
+    type Hasher = S::Hasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        self.0.build_hasher()
+    }
+}
+
+const POISONED: &str = "a shard was poisoned by a panic while it was locked";
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().expect(POISONED)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().expect(POISONED)
+}
+
+type Shard<K, V, S, M, A> = RwLock<<M as HashMapFamily>::Map<K, Option<V>, SharedState<S>, A>>;
+type Shards<K, V, S, M, A> = Box<[Shard<K, V, S, M, A>], A>;
+
+pub struct ShardedHashMap<
+    K,
+    V,
+    S = RandomState,
+    M: HashMapFamily = SimpleOAHashMapFamily,
+    A: Allocator + Clone = Global,
+> {
+    shards: Shards<K, V, S, M, A>,
+    s: Arc<S>,
+}
+
+impl<K: Eq + Hash, V> ShardedHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S, M, A> Default for ShardedHashMap<K, V, S, M, A>
+where
+    S: Default,
+    M: HashMapFamily,
+    A: Allocator + Clone + Default,
+{
+    fn default() -> Self {
+        Self::with_shards_capacity_and_hasher_in(DEFAULT_SHARDS, 0, S::default(), A::default())
+    }
+}
+
+impl<K, V, S, M: HashMapFamily> ShardedHashMap<K, V, S, M> {
+    pub fn with_hasher(state: S) -> Self {
+        Self::with_shards_and_hasher(DEFAULT_SHARDS, state)
+    }
+
+    /// Create a map with `shards` shards, which must be a power of two.
+    pub fn with_shards_and_hasher(shards: usize, state: S) -> Self {
+        Self::with_shards_capacity_and_hasher(shards, 0, state)
+    }
+
+    /// Create a map with `shards` shards, which must be a power of two, and room for
+    /// `capacity` evenly distributed elements.
+    pub fn with_shards_capacity_and_hasher(shards: usize, capacity: usize, state: S) -> Self {
+        Self::with_shards_capacity_and_hasher_in(shards, capacity, state, Global)
+    }
+}
+
+impl<K, V, S, M: HashMapFamily, A: Allocator + Clone> ShardedHashMap<K, V, S, M, A> {
+    /// Like [`ShardedHashMap::with_shards_capacity_and_hasher`], allocating with `alloc`.
+    pub fn with_shards_capacity_and_hasher_in(
+        shards: usize,
+        capacity: usize,
+        state: S,
+        alloc: A,
+    ) -> Self {
+        assert!(
+            shards.is_power_of_two(),
+            "number of shards must be a power of two, was {shards}"
+        );
+        let s = Arc::new(state);
+        let shard_capacity = capacity.div_ceil(shards);
+        let mut maps = Vec::with_capacity_in(shards, alloc.clone());
+        maps.extend((0..shards).map(|_| {
+            let state = SharedState(s.clone());
+            let map = M::Map::with_capacity_and_hasher_in(shard_capacity, state, alloc.clone());
+            RwLock::new(map)
+        }));
+        Self {
+            shards: maps.into_boxed_slice(),
+            s,
+        }
+    }
+}
+
+impl<K, V, S, M, A> ShardedHashMap<K, V, S, M, A>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    M: HashMapFamily,
+    A: Allocator + Clone,
+{
+    fn shard_of_elem(&self, key: &K) -> &Shard<K, V, S, M, A> {
+        // Use the upper bits, the shards use the lower ones.
+        let shard_bits = self.shards.len().trailing_zeros();
+        let hash = self.s.hash_one(key);
+        let shard = hash.checked_shr(64 - shard_bits).unwrap_or(0);
+        &self.shards[shard as usize]
+    }
+}
+
+impl<K, V, S, M, A> ConcurrentHashMap<K, V, S> for ShardedHashMap<K, V, S, M, A>
+where
+    M: HashMapFamily,
+    A: Allocator + Clone + Default,
+{
+    type Ref<'a>
+        = MappedRwLockReadGuard<'a, V>
+    where
+        Self: 'a;
+
+    fn with_hasher(state: S) -> Self {
+        Self::with_shards_capacity_and_hasher_in(DEFAULT_SHARDS, 0, state, A::default())
+    }
+
+    fn len(&self) -> usize {
+        self.shards.iter().map(|shard| read(shard).len()).sum()
+    }
+
+    fn get(&self, key: &K) -> Option<Self::Ref<'_>>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let shard = read(self.shard_of_elem(key));
+        RwLockReadGuard::filter_map(shard, |shard| shard.get(key)?.as_ref()).ok()
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        write(self.shard_of_elem(&key))
+            .insert(key, Some(value))
+            .flatten()
+    }
+
+    fn remove(&self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        write(self.shard_of_elem(key)).remove(key).flatten()
+    }
+
+    fn compute<R>(&self, key: K, f: impl FnOnce(&mut Option<V>) -> R) -> R
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut shard = write(self.shard_of_elem(&key));
+        // Keep the entry as far as `f` got with modifying it, even if `f` panics.
+        let result = match shard.get_mut(&key) {
+            Some(entry) => {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| f(entry)));
+                if entry.is_none() {
+                    shard.remove(&key);
+                }
+                result
+            }
+            None => {
+                let mut entry = None;
+                let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut entry)));
+                if entry.is_some() {
+                    shard.insert(key, entry);
+                }
+                result
+            }
+        };
+        // The shard is consistent, so unlock it before a panic of `f` can poison it.
+        drop(shard);
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+}
+
+type ShardIntoIter<K, V, S, M, A> =
+    <<M as HashMapFamily>::Map<K, Option<V>, SharedState<S>, A> as IntoIterator>::IntoIter;
+
+type IntoIterInner<K, V, S, M, A> = std::iter::FlatMap<
+    vec::IntoIter<Shard<K, V, S, M, A>, A>,
+    ShardIntoIter<K, V, S, M, A>,
+    fn(Shard<K, V, S, M, A>) -> ShardIntoIter<K, V, S, M, A>,
+>;
+
+pub struct IntoIter<K, V, S, M: HashMapFamily, A: Allocator + Clone = Global> {
+    shards: IntoIterInner<K, V, S, M, A>,
+}
+
+impl<K, V, S, M: HashMapFamily, A: Allocator + Clone> Iterator for IntoIter<K, V, S, M, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.shards.find_map(|(key, value)| Some((key, value?)))
+    }
+}
+
+impl<K, V, S, M: HashMapFamily, A: Allocator + Clone> IntoIterator
+    for ShardedHashMap<K, V, S, M, A>
+{
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, S, M, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            shards: self.shards.into_vec().into_iter().flat_map(|shard| {
+                let shard = shard.into_inner().expect(POISONED);
+                shard.into_iter()
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V, S, M, A> serde::Serialize for ShardedHashMap<K, V, S, M, A>
+where
+    K: serde::Serialize,
+    V: serde::Serialize,
+    M: HashMapFamily,
+    A: Allocator + Clone,
+{
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        use serde::ser::SerializeMap;
+
+        // Lock all shards for the whole time, so we see a consistent length and entries.
+        let shards = self.shards.iter().map(read).collect::<Vec<_>>();
+        let len = shards.iter().map(|shard| shard.len()).sum();
+        let mut map = serializer.serialize_map(Some(len))?;
+        for (key, value) in shards.iter().flat_map(|shard| shard.iter()) {
+            if let Some(value) = value {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V, S, M, A> super::serde_impls::DeserializeMap<K, V, S> for ShardedHashMap<K, V, S, M, A>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    M: HashMapFamily,
+    A: Allocator + Clone + Default,
+{
+    fn with_capacity_and_hasher(capacity: usize, state: S) -> Self {
+        Self::with_shards_capacity_and_hasher_in(DEFAULT_SHARDS, capacity, state, A::default())
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        ConcurrentHashMap::get(self, key).is_some()
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        ConcurrentHashMap::insert(self, key, value);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V, S, M, A> serde::Deserialize<'de> for ShardedHashMap<K, V, S, M, A>
+where
+    K: serde::Deserialize<'de> + Eq + Hash,
+    V: serde::Deserialize<'de>,
+    S: BuildHasher + Default,
+    M: HashMapFamily,
+    A: Allocator + Clone + Default,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use super::serde_impls::{DuplicateKeys, MapSeed};
+        serde::de::DeserializeSeed::deserialize(MapSeed::new(DuplicateKeys::Error), deserializer)
+    }
+}
+
+/// Sharded maps with shards from the family `M`.
+pub struct ShardedHashMapFamily<M = SimpleOAHashMapFamily>(PhantomData<M>);
+impl<M: HashMapFamily> ConcurrentHashMapFamily for ShardedHashMapFamily<M> {
+    type Map<K, V, S> = ShardedHashMap<K, V, S, M>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashmaps::{
+        robin_hood::RobinHoodHashMapFamily, simple_open_addressing::SimpleOAHashMapFamily,
+        swiss_table::SwissHashMapFamily, tests::run_concurrent_tests,
+    };
+
+    use super::{ShardedHashMap, ShardedHashMapFamily};
+    use crate::hashmaps::ConcurrentHashMap;
+    use std::{
+        hash::RandomState,
+        panic::{self, AssertUnwindSafe},
+    };
+
+    #[test]
+    fn do_tests() {
+        run_concurrent_tests::<ShardedHashMapFamily<SimpleOAHashMapFamily>>();
+        run_concurrent_tests::<ShardedHashMapFamily<SwissHashMapFamily>>();
+        run_concurrent_tests::<ShardedHashMapFamily<RobinHoodHashMapFamily>>();
+    }
+
+    #[test]
+    fn panic_in_compute() {
+        let m = ShardedHashMap::new();
+        m.insert(1, 1);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            m.compute(1, |entry| {
+                *entry = Some(2);
+                panic!("oh no");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(*m.get(&1).unwrap(), 2);
+        m.insert(2, 2);
+        assert_eq!(m.len(), 2);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            m.compute(2, |entry| {
+                *entry = None;
+                panic!("oh no");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(m.get(&2).is_none());
+        assert_eq!(m.len(), 1);
+    }
+
+    #[test]
+    fn compute_in_place() {
+        let m = ShardedHashMap::<u32, u32>::with_shards_and_hasher(1, RandomState::new());
+        m.insert(0, 0);
+        let capacity = || m.shards[0].read().unwrap().capacity();
+        let before = capacity();
+        for _ in 0..10_000 {
+            m.compute(0, |entry| *entry.as_mut().unwrap() += 1);
+        }
+        assert_eq!(*m.get(&0).unwrap(), 10_000);
+        assert_eq!(capacity(), before);
+        assert_eq!(m.len(), 1);
+    }
+}
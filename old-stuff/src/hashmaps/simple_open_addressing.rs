@@ -1,14 +1,82 @@
-use super::{HashMap, HashMapFamily};
+//! A very simple open addressing hash map with linear probing.
+//!
+//! The table always has a power of two number of buckets, so the home bucket of a key is
+//! just its hash masked with `len - 1`. Probing wraps around at the end of the table, and
+//! the table is grown before it gets fuller than its maximum load factor, so every probe
+//! sequence is guaranteed to eventually hit an empty bucket.
+//!
+//! Removal uses tombstones: a removed bucket is marked as [`Bucket::Tombstone`] instead of
+//! being emptied, so that probe sequences running over it keep going and still find keys that
+//! were placed behind it. Backward-shift deletion would avoid the tombstones, but it has to
+//! rehash every entry it moves to check whether it may be moved, which is not worth it here.
+//! Tombstones count towards the load of the table and are dropped whenever the table is rehashed.
+//!
+//! Linear probing degrades badly when many keys hash to the same bucket. With
+//! [`SimpleOAHashMap::enable_hashdos_protection`], an insertion that has to probe abnormally far
+//! switches the hasher to a new seed and rehashes the table.
+
+use super::{
+    capacity_overflow, handle_reserve,
+    hash_quality::{HashDosProtection, HashStats, Reseed},
+    HashMap, HashMapFamily,
+};
 use std::{
+    alloc::{Allocator, Global},
+    collections::TryReserveError,
     hash::{BuildHasher, Hash, RandomState},
     vec,
 };
 
-type Entry<K, V> = Option<(K, V)>;
+/// The number of buckets of the first allocation.
+const MIN_BUCKETS: usize = 8;
 
-pub struct SimpleOAHashMap<K, V, S = RandomState> {
-    buckets: Vec<Entry<K, V>>,
+/// The default maximum load factor as `(numerator, denominator)`.
+const DEFAULT_MAX_LOAD: (usize, usize) = (7, 8);
+
+enum Bucket<K, V> {
+    Empty,
+    Full(K, V),
+    Tombstone,
+}
+
+impl<K, V> Bucket<K, V> {
+    fn is_empty(&self) -> bool {
+        matches!(self, Bucket::Empty)
+    }
+
+    fn into_entry(self) -> Option<(K, V)> {
+        match self {
+            Bucket::Full(key, value) => Some((key, value)),
+            Bucket::Empty | Bucket::Tombstone => None,
+        }
+    }
+}
+
+/// All indices of a table of `buckets` buckets, starting from `start` and wrapping around at
+/// the end. `buckets` must be a power of two.
+pub(super) fn probe_seq(start: usize, buckets: usize) -> impl Iterator<Item = usize> {
+    let mask = buckets - 1;
+    (0..buckets).map(move |i| (start + i) & mask)
+}
+
+/// A table of `len` empty buckets, allocated with `alloc`.
+fn try_empty_buckets<K, V, A: Allocator>(
+    len: usize,
+    alloc: A,
+) -> Result<Vec<Bucket<K, V>, A>, TryReserveError> {
+    let mut buckets = Vec::new_in(alloc);
+    buckets.try_reserve_exact(len)?;
+    buckets.resize_with(len, || Bucket::Empty);
+    Ok(buckets)
+}
+
+pub struct SimpleOAHashMap<K, V, S = RandomState, A: Allocator = Global> {
+    buckets: Vec<Bucket<K, V>, A>,
     filled: usize,
+    tombstones: usize,
+    /// The maximum load factor as `(numerator, denominator)`.
+    max_load: (usize, usize),
+    protection: HashDosProtection<S>,
     s: S,
 }
 
@@ -16,39 +84,219 @@ impl<K: Eq + Hash, V> SimpleOAHashMap<K, V, RandomState> {
     pub fn new() -> Self {
         Self::with_hasher(RandomState::new())
     }
+
+    /// Create a map that can hold at least `capacity` elements without reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K, V, S> SimpleOAHashMap<K, V, S> {
+    pub fn with_hasher(state: S) -> Self {
+        Self::with_hasher_in(state, Global)
+    }
+
+    /// Create a map that can hold at least `capacity` elements without reallocating.
+    pub fn with_capacity_and_hasher(capacity: usize, state: S) -> Self {
+        Self::with_capacity_and_hasher_in(capacity, state, Global)
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> SimpleOAHashMap<K, V, S, A> {
+    pub fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            buckets: Vec::new_in(alloc),
+            filled: 0,
+            tombstones: 0,
+            max_load: DEFAULT_MAX_LOAD,
+            protection: HashDosProtection::disabled(),
+            s: state,
+        }
+    }
+
+    /// Create a map that can hold at least `capacity` elements without reallocating.
+    pub fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        let mut map = Self::with_hasher_in(state, alloc);
+        let buckets = handle_reserve(map.buckets_for(capacity));
+        map.buckets = handle_reserve(try_empty_buckets(buckets, map.buckets.allocator().clone()));
+        map
+    }
+
+    /// The number of elements the map can hold before it has to grow.
+    pub fn capacity(&self) -> usize {
+        let (numerator, denominator) = self.max_load;
+        self.buckets.len() * numerator / denominator
+    }
+
+    /// Set the maximum fraction of buckets that may be in use (by entries or tombstones)
+    /// before the table is grown. The default is 7/8.
+    ///
+    /// The new load factor is only applied on the next insertion.
+    pub fn set_max_load_factor(&mut self, numerator: usize, denominator: usize) {
+        assert!(
+            0 < numerator && numerator < denominator,
+            "load factor must be in (0, 1), was {numerator}/{denominator}"
+        );
+        self.max_load = (numerator, denominator);
+    }
+
+    /// Switch to a new hash seed and rehash whenever an insertion has to probe much further
+    /// than it would with a good hash function, which makes it hard to attack the map with
+    /// colliding keys.
+    ///
+    /// If the keys still collide with the new seed, they collide no matter the seed, and
+    /// the map stops reseeding instead of rehashing over and over.
+    pub fn enable_hashdos_protection(&mut self)
+    where
+        S: Reseed,
+    {
+        self.protection.enable();
+    }
+
+    /// The number of buckets required to hold `capacity` elements with the current load factor.
+    fn buckets_for(&self, capacity: usize) -> Result<usize, TryReserveError> {
+        if capacity == 0 {
+            return Ok(0);
+        }
+        let (numerator, denominator) = self.max_load;
+        // One more than the strict minimum, so that there is still room left
+        // after inserting `capacity` elements.
+        let required = capacity
+            .checked_mul(denominator)
+            .map(|buckets| buckets.div_ceil(numerator) + 1)
+            .and_then(usize::checked_next_power_of_two)
+            .ok_or_else(capacity_overflow)?;
+        Ok(required.max(MIN_BUCKETS))
+    }
+
+    /// Whether inserting `additional` more elements would push the load over the maximum
+    /// load factor.
+    fn needs_grow(&self, additional: usize) -> bool {
+        let (numerator, denominator) = self.max_load;
+        let used = (self.filled + self.tombstones).saturating_add(additional);
+        used.saturating_mul(denominator) > self.buckets.len() * numerator
+    }
+
+    /// All bucket indices, starting from `start` and wrapping around at the end of the table.
+    fn probe_seq(&self, start: usize) -> impl Iterator<Item = usize> {
+        probe_seq(start, self.buckets.len())
+    }
 }
 
-impl<K: Eq + Hash, V, S: BuildHasher> SimpleOAHashMap<K, V, S> {
+impl<K: Eq + Hash, V, S: BuildHasher, A: Allocator + Clone> SimpleOAHashMap<K, V, S, A> {
     fn bucket_of_elem(&self, key: &K) -> usize {
         assert_ne!(self.buckets.len(), 0, "cannot compute bucket of empty map");
-        let hash = self.s.hash_one(&key) as usize;
-        hash % self.buckets.len()
+        let hash = self.s.hash_one(key) as usize;
+        hash & (self.buckets.len() - 1)
+    }
+
+    /// The index of the bucket containing `key`, if there is one.
+    fn find(&self, key: &K) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+        let bucket = self.bucket_of_elem(key);
+
+        self.probe_seq(bucket)
+            .take_while(|&i| !self.buckets[i].is_empty())
+            .find(|&i| matches!(&self.buckets[i], Bucket::Full(elem_key, _) if elem_key == key))
+    }
+
+    /// How well the keys are distributed over the table. Tombstones count as occupied
+    /// buckets, since probe sequences have to go past them.
+    pub fn stats(&self) -> HashStats {
+        let mask = self.buckets.len().wrapping_sub(1);
+        let probe_lens = self.buckets.iter().enumerate().filter_map(|(i, bucket)| {
+            let Bucket::Full(key, _) = bucket else {
+                return None;
+            };
+            let home = self.s.hash_one(key) as usize;
+            Some(i.wrapping_sub(home) & mask)
+        });
+        HashStats::new(
+            self.buckets.iter().map(|bucket| !bucket.is_empty()),
+            probe_lens,
+        )
     }
 
     fn grow(&mut self) {
         let len = self.buckets.len();
-        let new = if len == 0 { 8 } else { len * 2 };
-        let old = IntoIter::new(std::mem::take(&mut self.buckets));
-        let new_buckets = (0..new).map(|_| None).collect();
-        self.buckets = new_buckets;
+        // If a good chunk of the used buckets are tombstones, cleaning them up
+        // frees enough space without making the table any bigger.
+        let new = if len == 0 {
+            MIN_BUCKETS
+        } else if self.tombstones >= len / 4 {
+            len
+        } else {
+            len * 2
+        };
+        self.rehash(new);
+    }
+
+    /// Reinsert all entries into a fresh table of `new` buckets, dropping all tombstones.
+    fn rehash(&mut self, new: usize) {
+        handle_reserve(self.try_rehash(new));
+    }
+
+    fn try_rehash(&mut self, new: usize) -> Result<(), TryReserveError> {
+        let buckets = try_empty_buckets(new, self.buckets.allocator().clone())?;
+        self.rehash_into(buckets);
+        Ok(())
+    }
+
+    /// Move all entries into `buckets`, which must be empty and big enough to hold them.
+    fn rehash_into(&mut self, buckets: Vec<Bucket<K, V>, A>) {
+        debug_assert!(buckets.len().is_power_of_two());
+        let old = IntoIter::new(std::mem::replace(&mut self.buckets, buckets));
+        self.filled = 0;
+        self.tombstones = 0;
+        // Don't reseed in the middle of rehashing.
+        let protection = std::mem::replace(&mut self.protection, HashDosProtection::disabled());
         self.extend(old);
+        self.protection = protection;
     }
-}
 
-impl<K: Eq + Hash, V, S: BuildHasher> Extend<(K, V)> for SimpleOAHashMap<K, V, S> {
-    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
-        iter.into_iter()
-            .for_each(|(key, value)| drop(self.insert(key, value)));
+    /// Switch to a new hash seed after an abnormally long probe sequence.
+    fn reseed_and_rehash(&mut self) {
+        // Allocate before reseeding, as the entries can't be found with the new seed until
+        // they are rehashed. Without the memory, the map just stays slow.
+        let alloc = self.buckets.allocator().clone();
+        let Ok(buckets) = try_empty_buckets(self.buckets.len(), alloc) else {
+            return;
+        };
+        self.protection.reseed(&mut self.s);
+        self.rehash_into(buckets);
+        let stats = self.stats();
+        self.protection.check_reseed(&stats);
     }
 }
 
-impl<K, V, S> super::HashMap<K, V, S> for SimpleOAHashMap<K, V, S> {
-    fn with_hasher(state: S) -> Self {
-        Self {
-            buckets: Vec::new(),
-            filled: 0,
-            s: state,
-        }
+impl<K, V, S, A: Allocator + Clone> super::HashMap<K, V, S, A> for SimpleOAHashMap<K, V, S, A> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        // The inherent methods, which also work without importing the trait.
+        Self::with_hasher_in(state, alloc)
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        Self::with_capacity_and_hasher_in(capacity, state, alloc)
+    }
+
+    fn allocator(&self) -> &A {
+        self.buckets.allocator()
+    }
+
+    fn hasher(&self) -> &S {
+        &self.s
     }
 
     fn len(&self) -> usize {
@@ -64,20 +312,21 @@ impl<K, V, S> super::HashMap<K, V, S> for SimpleOAHashMap<K, V, S> {
         K: Eq + Hash,
         S: BuildHasher,
     {
-        if self.is_empty() {
-            return None;
+        match &self.buckets[self.find(key)?] {
+            Bucket::Full(_, value) => Some(value),
+            Bucket::Empty | Bucket::Tombstone => unreachable!("found a bucket without an entry"),
         }
-        let bucket = self.bucket_of_elem(&key);
-
-        let result = self.buckets[bucket..]
-            .iter()
-            .take_while(|elem| elem.is_some())
-            .find(|elem| matches!(elem, Some((elem_key, _)) if elem_key == key));
+    }
 
-        if let Some(Some((_, value))) = result {
-            Some(value)
-        } else {
-            None
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let index = self.find(key)?;
+        match &mut self.buckets[index] {
+            Bucket::Full(_, value) => Some(value),
+            Bucket::Empty | Bucket::Tombstone => unreachable!("found a bucket without an entry"),
         }
     }
 
@@ -86,40 +335,157 @@ impl<K, V, S> super::HashMap<K, V, S> for SimpleOAHashMap<K, V, S> {
         K: Eq + Hash,
         S: BuildHasher,
     {
-        if self.filled >= self.buckets.len() {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(std::mem::replace(old, value));
+        }
+
+        // Only a new entry needs a bucket, so overwriting never grows the table.
+        // Tombstones take up buckets just like entries do, so they count towards the load.
+        if self.needs_grow(1) {
             self.grow();
         }
-        loop {
-            let bucket = self.bucket_of_elem(&key);
-            let bucket = self.buckets[bucket..].iter_mut().find(|bucket| {
-                bucket.is_none() || matches!(bucket, Some((elem_key, _)) if *elem_key == key)
-            });
-            if let Some(bucket) = bucket {
-                if bucket.is_none() {
-                    self.filled += 1;
-                }
-                let before = std::mem::replace(bucket, Some((key, value)));
-                return before.map(|(_, v)| v);
-            } else {
-                self.grow();
-            }
+        let home = self.bucket_of_elem(&key);
+        // The load factor guarantees that there is at least one empty bucket.
+        let free = self
+            .probe_seq(home)
+            .find(|&i| !matches!(self.buckets[i], Bucket::Full(..)))
+            .expect("no free bucket found in the table");
+        let bucket = &mut self.buckets[free];
+        if let Bucket::Tombstone = bucket {
+            self.tombstones -= 1;
+        }
+        *bucket = Bucket::Full(key, value);
+        self.filled += 1;
+
+        let probe_len = free.wrapping_sub(home) & (self.buckets.len() - 1);
+        if self.protection.is_triggered(probe_len, self.buckets.len()) {
+            self.reseed_and_rehash();
+        }
+        None
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let bucket = self.find(key)?;
+        let removed = std::mem::replace(&mut self.buckets[bucket], Bucket::Tombstone);
+        self.filled -= 1;
+        self.tombstones += 1;
+        removed.into_entry().map(|(_, value)| value)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if !self.needs_grow(additional) {
+            return Ok(());
+        }
+        // Rehashing drops the tombstones, so only the entries need room in the new table.
+        let required = self
+            .filled
+            .checked_add(additional)
+            .ok_or_else(capacity_overflow)?;
+        let new = self.buckets_for(required)?.max(self.buckets.len());
+        self.try_rehash(new)
+    }
+
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if let Some(old) = self.get_mut(&key) {
+            return Ok(Some(std::mem::replace(old, value)));
+        }
+        self.try_reserve(1)?;
+        Ok(self.insert(key, value))
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            buckets: self.buckets.iter(),
+            remaining: self.filled,
         }
     }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        IterMut {
+            buckets: self.buckets.iter_mut(),
+            remaining: self.filled,
+        }
+    }
+}
+
+impl_std_traits!(SimpleOAHashMap);
+
+pub struct Iter<'a, K, V> {
+    buckets: std::slice::Iter<'a, Bucket<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.find_map(|bucket| match bucket {
+            Bucket::Full(key, value) => Some((key, value)),
+            Bucket::Empty | Bucket::Tombstone => None,
+        })?;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub struct IterMut<'a, K, V> {
+    buckets: std::slice::IterMut<'a, Bucket<K, V>>,
+    remaining: usize,
 }
 
-pub struct IntoIter<K, V> {
-    buckets: std::iter::FilterMap<vec::IntoIter<Entry<K, V>>, fn(Entry<K, V>) -> Option<(K, V)>>,
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.find_map(|bucket| match bucket {
+            Bucket::Full(key, value) => Some((&*key, value)),
+            Bucket::Empty | Bucket::Tombstone => None,
+        })?;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
+type IntoIterInner<K, V, A> =
+    std::iter::FilterMap<vec::IntoIter<Bucket<K, V>, A>, fn(Bucket<K, V>) -> Option<(K, V)>>;
+
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    buckets: IntoIterInner<K, V, A>,
 }
 
-impl<K, V> IntoIter<K, V> {
-    fn new(buckets: Vec<Entry<K, V>>) -> Self {
+impl<K, V, A: Allocator> IntoIter<K, V, A> {
+    fn new(buckets: Vec<Bucket<K, V>, A>) -> Self {
         IntoIter {
-            buckets: buckets.into_iter().filter_map(std::convert::identity),
+            buckets: buckets.into_iter().filter_map(Bucket::into_entry),
         }
     }
 }
 
-impl<K, V> Iterator for IntoIter<K, V> {
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -127,10 +493,10 @@ impl<K, V> Iterator for IntoIter<K, V> {
     }
 }
 
-impl<K, V, S> IntoIterator for SimpleOAHashMap<K, V, S> {
+impl<K, V, S, A: Allocator> IntoIterator for SimpleOAHashMap<K, V, S, A> {
     type Item = (K, V);
 
-    type IntoIter = IntoIter<K, V>;
+    type IntoIter = IntoIter<K, V, A>;
 
     fn into_iter(self) -> Self::IntoIter {
         IntoIter::new(self.buckets)
@@ -139,13 +505,206 @@ impl<K, V, S> IntoIterator for SimpleOAHashMap<K, V, S> {
 
 pub struct SimpleOAHashMapFamily;
 impl HashMapFamily for SimpleOAHashMapFamily {
-    type Map<K, V, S> = SimpleOAHashMap<K, V, S>;
+    type Map<K, V, S, A: Allocator + Clone> = SimpleOAHashMap<K, V, S, A>;
 }
 
 #[cfg(test)]
 mod tests {
+    use std::hash::{BuildHasherDefault, DefaultHasher, Hasher};
+
+    use crate::hashmaps::{tests::WeakSeedState, HashMap};
+
+    use super::SimpleOAHashMap;
+
     #[test]
     fn do_tests() {
         crate::hashmaps::tests::run_tests::<super::SimpleOAHashMapFamily>();
     }
+
+    #[test]
+    fn rehash_drops_tombstones() {
+        let mut m = SimpleOAHashMap::new();
+        for i in 0..6 {
+            m.insert(i, i);
+        }
+        for i in 0..6 {
+            m.remove(&i);
+        }
+        assert_eq!(m.tombstones, 6);
+
+        // Filling the table up again must clean up the tombstones instead of growing forever.
+        for i in 6..14 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.len(), 8);
+        assert_eq!(m.tombstones, 0);
+    }
+
+    #[derive(Default)]
+    struct LastBucketHasher;
+    impl Hasher for LastBucketHasher {
+        fn finish(&self) -> u64 {
+            u64::MAX
+        }
+        fn write(&mut self, _bytes: &[u8]) {}
+    }
+
+    #[test]
+    fn stats() {
+        let mut m = SimpleOAHashMap::with_hasher(BuildHasherDefault::<LastBucketHasher>::default());
+        for i in 0..3 {
+            m.insert(i, i);
+        }
+        let stats = m.stats();
+        assert_eq!(stats.probe_lengths, [1, 1, 1]);
+        // Buckets 7, 0 and 1 form a single cluster, wrapping around.
+        assert_eq!((stats.clusters, stats.max_cluster_len), (1, 3));
+
+        m.remove(&1);
+        let stats = m.stats();
+        assert_eq!(stats.len, 2);
+        assert_eq!(stats.probe_lengths, [1, 0, 1]);
+        assert_eq!(
+            stats.max_cluster_len, 3,
+            "tombstones still occupy their bucket"
+        );
+    }
+
+    #[test]
+    fn hashdos_protection_reseeds() {
+        let state = WeakSeedState {
+            seed: 0,
+            reseed_helps: true,
+        };
+        let mut m = SimpleOAHashMap::with_hasher(state.clone());
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.hasher().seed, 1);
+        assert!(!m.stats().is_degenerate(), "{}", m.stats());
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+
+        let mut unprotected = SimpleOAHashMap::with_hasher(state);
+        for i in 0..1000 {
+            unprotected.insert(i, i);
+        }
+        assert!(unprotected.stats().is_degenerate());
+    }
+
+    #[test]
+    fn hashdos_protection_gives_up() {
+        let mut m = SimpleOAHashMap::with_hasher(WeakSeedState::default());
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        // Reseeding didn't help, so the map stopped trying, but still works.
+        assert!(!m.protection.is_enabled());
+        assert!(m.stats().is_degenerate());
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+    }
+
+    #[test]
+    fn borrowing_iterators() {
+        let mut m = (0..10).map(|i| (i, i)).collect::<SimpleOAHashMap<_, _>>();
+        m.remove(&3);
+
+        let mut iter = m.iter();
+        assert_eq!(iter.len(), 9);
+        iter.next();
+        assert_eq!(iter.size_hint(), (8, Some(8)));
+
+        for (_, value) in &mut m {
+            *value *= 2;
+        }
+        let mut entries = m.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>();
+        entries.sort();
+        let expected = (0..10).filter(|&i| i != 3).map(|i| (i, i * 2));
+        assert_eq!(entries, expected.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn std_traits() {
+        let mut m = SimpleOAHashMap::<_, _, BuildHasherDefault<DefaultHasher>>::default();
+        m.insert("a", 1);
+        assert_eq!(format!("{m:?}"), r#"{"a": 1}"#);
+        assert_eq!(m[&"a"], 1);
+
+        let mut other = m.clone();
+        assert_eq!(m, other);
+        other.insert("b", 2);
+        assert_ne!(m, other);
+        other.remove(&"b");
+        assert_eq!(m, other);
+        other.insert("a", 2);
+        assert_ne!(m, other);
+
+        let collected = [("a", 1)].into_iter().collect();
+        assert_eq!(m, collected);
+    }
+
+    #[test]
+    #[should_panic = "key not found in map"]
+    fn index_missing() {
+        let m = SimpleOAHashMap::<i32, i32>::new();
+        let _ = m[&0];
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut m = SimpleOAHashMap::with_hasher(BuildHasherDefault::<LastBucketHasher>::default());
+        for i in 0..7 {
+            m.insert(i, i);
+        }
+        // All keys hash to the very last bucket, but probing wraps around instead of growing.
+        assert_eq!(m.buckets.len(), 8);
+        for i in 0..7 {
+            assert_eq!(m.get(&i), Some(&i));
+        }
+
+        m.insert(7, 7);
+        assert_eq!(m.buckets.len(), 16);
+    }
+
+    #[test]
+    fn load_factor() {
+        let mut m = SimpleOAHashMap::new();
+        m.set_max_load_factor(1, 2);
+        for i in 0..4 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.buckets.len(), 8);
+        m.insert(4, 4);
+        assert_eq!(m.buckets.len(), 16);
+        assert_eq!(m.capacity(), 8);
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut m = SimpleOAHashMap::with_capacity(100);
+        let buckets = m.buckets.len();
+        assert!(buckets.is_power_of_two());
+        assert!(m.capacity() >= 100);
+
+        for i in 0..100 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.buckets.len(), buckets, "map was reallocated");
+    }
+
+    #[test]
+    fn overwrite_full_table() {
+        let mut m = SimpleOAHashMap::new();
+        m.set_max_load_factor(1, 2);
+        while !m.needs_grow(1) {
+            m.insert(m.len(), 0);
+        }
+        let capacity = m.capacity();
+        for i in 0..m.len() {
+            assert_eq!(m.insert(i, 1), Some(0));
+            assert_eq!(m.try_insert(i, 2), Ok(Some(1)));
+        }
+        assert_eq!(m.capacity(), capacity);
+    }
 }
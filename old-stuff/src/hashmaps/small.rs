@@ -0,0 +1,430 @@
+//! A hash map for a handful of entries.
+//!
+//! Up to `N` entries are stored inline and found with a linear scan, so small maps neither hash
+//! their keys nor allocate. Once the map has to hold more than `N` entries, it moves all of them
+//! into a [`SimpleOAHashMap`] and keeps using that, even if it shrinks again later.
+
+use super::{
+    capacity_overflow, handle_reserve,
+    hash_quality::{HashStats, Reseed},
+    simple_open_addressing::{self, SimpleOAHashMap},
+    HashMap, HashMapFamily,
+};
+use std::{
+    alloc::{Allocator, Global},
+    collections::TryReserveError,
+    hash::{BuildHasher, Hash, RandomState},
+    mem::{self, MaybeUninit},
+    ptr, slice,
+};
+
+/// Up to `N` values, stored inline.
+struct InlineVec<T, const N: usize> {
+    /// The first `len` values are initialized.
+    values: [MaybeUninit<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> InlineVec<T, N> {
+    fn new() -> Self {
+        Self {
+            values: [const { MaybeUninit::uninit() }; N],
+            len: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[T] {
+        // SAFETY: The first `len` values are initialized.
+        unsafe { slice::from_raw_parts(self.values.as_ptr().cast(), self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [T] {
+        // SAFETY: The first `len` values are initialized.
+        unsafe { slice::from_raw_parts_mut(self.values.as_mut_ptr().cast(), self.len) }
+    }
+
+    /// Append `value`, or give it back if there is no room left.
+    fn push(&mut self, value: T) -> Result<(), T> {
+        let Some(slot) = self.values.get_mut(self.len) else {
+            return Err(value);
+        };
+        slot.write(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        // SAFETY: The value was initialized, and is no longer counted in `len`.
+        Some(unsafe { self.values[self.len].assume_init_read() })
+    }
+
+    /// Remove the value at `index`, replacing it with the last one.
+    fn swap_remove(&mut self, index: usize) -> T {
+        let last = self.pop().expect("swap_remove on an empty InlineVec");
+        if index == self.len {
+            return last;
+        }
+        mem::replace(&mut self.as_mut_slice()[index], last)
+    }
+}
+
+impl<T, const N: usize> Drop for InlineVec<T, N> {
+    fn drop(&mut self) {
+        // SAFETY: The first `len` values are initialized, and are never used again.
+        unsafe { ptr::drop_in_place(self.as_mut_slice()) }
+    }
+}
+
+pub struct SmallHashMap<K, V, const N: usize, S = RandomState, A: Allocator = Global> {
+    /// The entries, as long as the map didn't spill.
+    inline: InlineVec<(K, V), N>,
+    /// Holds the hasher and the allocator, and all entries once the map spilled. It doesn't
+    /// allocate before that, so the map spilled if and only if the table has any capacity.
+    table: SimpleOAHashMap<K, V, S, A>,
+}
+
+impl<K: Eq + Hash, V, const N: usize> SmallHashMap<K, V, N, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, const N: usize, S> SmallHashMap<K, V, N, S> {
+    pub fn with_hasher(state: S) -> Self {
+        HashMap::with_hasher_in(state, Global)
+    }
+}
+
+impl<K, V, const N: usize, S, A: Allocator + Clone> SmallHashMap<K, V, N, S, A> {
+    /// Whether the entries moved to the heap.
+    pub fn spilled(&self) -> bool {
+        self.table.capacity() != 0
+    }
+
+    /// See [`SimpleOAHashMap::enable_hashdos_protection`]. The inline entries are found by a
+    /// linear scan, so this only affects the map once it spilled.
+    pub fn enable_hashdos_protection(&mut self)
+    where
+        S: Reseed,
+    {
+        self.table.enable_hashdos_protection();
+    }
+}
+
+impl<K: Eq + Hash, V, const N: usize, S: BuildHasher, A: Allocator + Clone>
+    SmallHashMap<K, V, N, S, A>
+{
+    /// How well the keys are distributed over the table, or `None` if the map didn't spill.
+    pub fn stats(&self) -> Option<HashStats> {
+        self.spilled().then(|| self.table.stats())
+    }
+
+    /// Move all inline entries into the table, with room for `additional` more entries.
+    fn try_spill(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let required = self
+            .inline
+            .len
+            .checked_add(additional)
+            .ok_or_else(capacity_overflow)?;
+        // Allocate before moving anything, so that the map is unchanged if that fails.
+        self.table.try_reserve(required)?;
+        while let Some((key, value)) = self.inline.pop() {
+            self.table.insert(key, value);
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, const N: usize, S, A: Allocator + Clone> HashMap<K, V, S, A>
+    for SmallHashMap<K, V, N, S, A>
+{
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            inline: InlineVec::new(),
+            table: SimpleOAHashMap::with_hasher_in(state, alloc),
+        }
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        let table = if capacity > N {
+            SimpleOAHashMap::with_capacity_and_hasher_in(capacity, state, alloc)
+        } else {
+            SimpleOAHashMap::with_hasher_in(state, alloc)
+        };
+        Self {
+            inline: InlineVec::new(),
+            table,
+        }
+    }
+
+    fn allocator(&self) -> &A {
+        self.table.allocator()
+    }
+
+    fn hasher(&self) -> &S {
+        self.table.hasher()
+    }
+
+    fn len(&self) -> usize {
+        self.inline.len + self.table.len()
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if self.spilled() {
+            return self.table.get(key);
+        }
+        self.inline
+            .as_slice()
+            .iter()
+            .find(|(elem_key, _)| elem_key == key)
+            .map(|(_, value)| value)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if self.spilled() {
+            return self.table.get_mut(key);
+        }
+        self.inline
+            .as_mut_slice()
+            .iter_mut()
+            .find(|(elem_key, _)| elem_key == key)
+            .map(|(_, value)| value)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        handle_reserve(self.try_insert(key, value))
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if self.spilled() {
+            return self.table.remove(key);
+        }
+        let index = self
+            .inline
+            .as_slice()
+            .iter()
+            .position(|(elem_key, _)| elem_key == key)?;
+        Some(self.inline.swap_remove(index).1)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if self.spilled() {
+            self.table.try_reserve(additional)
+        } else if self.inline.len.saturating_add(additional) > N {
+            self.try_spill(additional)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if !self.spilled() {
+            let existing = self
+                .inline
+                .as_mut_slice()
+                .iter_mut()
+                .find(|(elem_key, _)| *elem_key == key);
+            if let Some((_, old)) = existing {
+                return Ok(Some(mem::replace(old, value)));
+            }
+            let Err((key, value)) = self.inline.push((key, value)) else {
+                return Ok(None);
+            };
+            self.try_spill(1)?;
+            return Ok(self.table.insert(key, value));
+        }
+        self.table.try_insert(key, value)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        if self.spilled() {
+            Iter::Table(self.table.iter())
+        } else {
+            Iter::Inline(self.inline.as_slice().iter())
+        }
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        if self.spilled() {
+            IterMut::Table(self.table.iter_mut())
+        } else {
+            IterMut::Inline(self.inline.as_mut_slice().iter_mut())
+        }
+    }
+}
+
+impl_std_traits!([const N: usize] SmallHashMap<K, V, N, S, A>);
+
+pub enum Iter<'a, K, V> {
+    Inline(slice::Iter<'a, (K, V)>),
+    Table(simple_open_addressing::Iter<'a, K, V>),
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Inline(entries) => entries.next().map(|(key, value)| (key, value)),
+            Iter::Table(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Inline(entries) => entries.size_hint(),
+            Iter::Table(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub enum IterMut<'a, K, V> {
+    Inline(slice::IterMut<'a, (K, V)>),
+    Table(simple_open_addressing::IterMut<'a, K, V>),
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            IterMut::Inline(entries) => entries.next().map(|(key, value)| (&*key, value)),
+            IterMut::Table(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            IterMut::Inline(entries) => entries.size_hint(),
+            IterMut::Table(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
+pub struct IntoIter<K, V, const N: usize, A: Allocator = Global> {
+    inline: InlineVec<(K, V), N>,
+    table: simple_open_addressing::IntoIter<K, V, A>,
+}
+
+impl<K, V, const N: usize, A: Allocator> Iterator for IntoIter<K, V, N, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inline.pop().or_else(|| self.table.next())
+    }
+}
+
+impl<K, V, const N: usize, S, A: Allocator> IntoIterator for SmallHashMap<K, V, N, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, N, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inline: self.inline,
+            table: self.table.into_iter(),
+        }
+    }
+}
+
+pub struct SmallHashMapFamily<const N: usize>;
+impl<const N: usize> HashMapFamily for SmallHashMapFamily<N> {
+    type Map<K, V, S, A: Allocator + Clone> = SmallHashMap<K, V, N, S, A>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashmaps::{tests::WeakSeedState, HashMap};
+
+    use super::SmallHashMap;
+
+    #[test]
+    fn do_tests() {
+        crate::hashmaps::tests::run_tests::<super::SmallHashMapFamily<8>>();
+        crate::hashmaps::tests::run_tests::<super::SmallHashMapFamily<0>>();
+    }
+
+    #[test]
+    fn spills_when_full() {
+        let mut m = SmallHashMap::<_, _, 4>::new();
+        for i in 0..4 {
+            m.insert(i, i);
+        }
+        m.insert(0, 10);
+        assert!(!m.spilled());
+        assert_eq!(m.remove(&1), Some(1));
+        assert_eq!(m.get(&3), Some(&3), "swap_remove lost the last entry");
+
+        m.insert(4, 4);
+        m.insert(5, 5);
+        assert!(m.spilled());
+        assert_eq!(m.len(), 5);
+        assert!([0, 2, 3, 4, 5].iter().all(|i| m.get(i).is_some()));
+
+        // The map doesn't move back inline once it spilled.
+        for i in 0..6 {
+            m.remove(&i);
+        }
+        assert!(m.spilled());
+        assert!(m.is_empty());
+    }
+
+    #[test]
+    fn hashdos_protection_after_spilling() {
+        let state = WeakSeedState {
+            seed: 0,
+            reseed_helps: true,
+        };
+        let mut m = SmallHashMap::<_, _, 4, _>::with_hasher(state);
+        m.enable_hashdos_protection();
+        for i in 0..4 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.stats(), None);
+        for i in 4..1000 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.hasher().seed, 1);
+        assert!(!m.stats().unwrap().is_degenerate());
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+    }
+}
@@ -0,0 +1,741 @@
+//! A SwissTable-style hash map, like `hashbrown` (and therefore `std`) uses.
+//!
+//! Next to the slots there is an array of control bytes, one per slot. A control byte is
+//! either [`EMPTY`], [`DELETED`] or, for a full slot, the top 7 bits of the hash of its key (`h2`).
+//! Probing happens in groups of [`GROUP_WIDTH`] control bytes at once: all slots in a group that
+//! could contain the key are found by comparing the whole group against `h2`, which is a single
+//! SSE2 instruction on x86_64. Other platforms use a portable fallback that compares byte by byte.
+//!
+//! To allow loading a full group starting at any slot, the first [`GROUP_WIDTH`] control bytes
+//! are mirrored after the end of the control bytes. This requires the table to have at least
+//! [`GROUP_WIDTH`] slots.
+//!
+//! The probe length of an entry counts the slots of all groups before the one it is in, plus
+//! its position in that group. It is what [`SwissHashMap::stats`] reports and what the HashDoS
+//! protection looks at.
+
+use super::{
+    capacity_overflow, handle_reserve,
+    hash_quality::{HashDosProtection, HashStats, Reseed},
+    HashMap, HashMapFamily,
+};
+use std::{
+    alloc::{Allocator, Global},
+    collections::TryReserveError,
+    hash::{BuildHasher, Hash, RandomState},
+    mem::MaybeUninit,
+};
+
+/// The control byte of a slot that has never been used.
+const EMPTY: u8 = 0b1111_1111;
+/// The control byte of a slot whose entry has been removed.
+const DELETED: u8 = 0b1000_0000;
+
+const GROUP_WIDTH: usize = 16;
+
+fn is_full(ctrl: u8) -> bool {
+    ctrl & 0x80 == 0
+}
+
+/// The top 7 bits of the hash, stored in the control byte.
+fn h2(hash: u64) -> u8 {
+    (hash >> 57) as u8
+}
+
+/// The number of elements that fit into a table of `buckets` slots.
+fn capacity_for_buckets(buckets: usize) -> usize {
+    buckets / 8 * 7
+}
+
+/// The number of buckets needed to hold `capacity` elements.
+fn buckets_for(capacity: usize) -> Result<usize, TryReserveError> {
+    let buckets = capacity
+        .checked_mul(8)
+        .map(|buckets| buckets.div_ceil(7))
+        .and_then(usize::checked_next_power_of_two)
+        .ok_or_else(capacity_overflow)?;
+    Ok(buckets.max(GROUP_WIDTH))
+}
+
+/// A set of positions within a group.
+#[derive(Clone, Copy)]
+struct BitMask(u16);
+
+impl BitMask {
+    fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    fn lowest_set_bit(self) -> Option<usize> {
+        if self.any() {
+            Some(self.0.trailing_zeros() as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl Iterator for BitMask {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bit = self.lowest_set_bit()?;
+        self.0 &= self.0 - 1;
+        Some(bit)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+mod group {
+    use super::{BitMask, EMPTY, GROUP_WIDTH};
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+    };
+
+    /// A group of control bytes, compared with SSE2.
+    pub(super) struct Group(__m128i);
+
+    impl Group {
+        /// Load the group starting at `ctrl[0]`.
+        pub(super) fn load(ctrl: &[u8]) -> Self {
+            assert!(ctrl.len() >= GROUP_WIDTH);
+            // SAFETY: We just checked that `ctrl` is valid for reads of a full group.
+            //         `_mm_loadu_si128` does not need any alignment.
+            Self(unsafe { _mm_loadu_si128(ctrl.as_ptr().cast::<__m128i>()) })
+        }
+
+        pub(super) fn match_byte(&self, byte: u8) -> BitMask {
+            // SAFETY: SSE2 is statically enabled.
+            unsafe {
+                let cmp = _mm_cmpeq_epi8(self.0, _mm_set1_epi8(byte as i8));
+                BitMask(_mm_movemask_epi8(cmp) as u16)
+            }
+        }
+
+        pub(super) fn match_empty(&self) -> BitMask {
+            self.match_byte(EMPTY)
+        }
+
+        pub(super) fn match_empty_or_deleted(&self) -> BitMask {
+            // Both `EMPTY` and `DELETED` have the top bit set, which is exactly what
+            // `movemask` extracts.
+            // SAFETY: SSE2 is statically enabled.
+            unsafe { BitMask(_mm_movemask_epi8(self.0) as u16) }
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+mod group {
+    use super::{BitMask, EMPTY, GROUP_WIDTH};
+
+    /// A group of control bytes, compared one byte at a time.
+    pub(super) struct Group([u8; GROUP_WIDTH]);
+
+    impl Group {
+        /// Load the group starting at `ctrl[0]`.
+        pub(super) fn load(ctrl: &[u8]) -> Self {
+            Self(ctrl[..GROUP_WIDTH].try_into().unwrap())
+        }
+
+        fn match_by(&self, f: impl Fn(u8) -> bool) -> BitMask {
+            let mut mask = 0;
+            for (i, &ctrl) in self.0.iter().enumerate() {
+                mask |= u16::from(f(ctrl)) << i;
+            }
+            BitMask(mask)
+        }
+
+        pub(super) fn match_byte(&self, byte: u8) -> BitMask {
+            self.match_by(|ctrl| ctrl == byte)
+        }
+
+        pub(super) fn match_empty(&self) -> BitMask {
+            self.match_byte(EMPTY)
+        }
+
+        pub(super) fn match_empty_or_deleted(&self) -> BitMask {
+            self.match_by(|ctrl| ctrl & 0x80 != 0)
+        }
+    }
+}
+
+use group::Group;
+
+/// The table itself, without the hasher.
+struct RawTable<K, V, A: Allocator> {
+    /// `buckets + GROUP_WIDTH` control bytes, or none if nothing has been allocated yet.
+    ctrl: Box<[u8], A>,
+    slots: Box<[MaybeUninit<(K, V)>], A>,
+    items: usize,
+    /// The number of `EMPTY` slots that may still be filled before the table has to grow.
+    growth_left: usize,
+}
+
+impl<K, V, A: Allocator + Clone> RawTable<K, V, A> {
+    fn new_in(alloc: A) -> Self {
+        Self {
+            ctrl: Box::new_in([], alloc.clone()),
+            slots: Box::new_in([], alloc),
+            items: 0,
+            growth_left: 0,
+        }
+    }
+
+    fn try_with_buckets_in(buckets: usize, alloc: A) -> Result<Self, TryReserveError> {
+        debug_assert!(buckets.is_power_of_two() && buckets >= GROUP_WIDTH);
+        // Both vectors are allocated with their exact length, so turning them into boxed
+        // slices doesn't reallocate.
+        let mut ctrl = Vec::new_in(alloc.clone());
+        ctrl.try_reserve_exact(buckets + GROUP_WIDTH)?;
+        ctrl.resize(buckets + GROUP_WIDTH, EMPTY);
+        let mut slots = Vec::new_in(alloc);
+        slots.try_reserve_exact(buckets)?;
+        slots.resize_with(buckets, MaybeUninit::uninit);
+        Ok(Self {
+            ctrl: ctrl.into_boxed_slice(),
+            slots: slots.into_boxed_slice(),
+            items: 0,
+            growth_left: capacity_for_buckets(buckets),
+        })
+    }
+}
+
+impl<K, V, A: Allocator> RawTable<K, V, A> {
+    fn allocator(&self) -> &A {
+        Box::allocator(&self.slots)
+    }
+
+    fn buckets(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn bucket_mask(&self) -> usize {
+        self.buckets() - 1
+    }
+
+    fn set_ctrl(&mut self, index: usize, ctrl: u8) {
+        // The first `GROUP_WIDTH` control bytes are mirrored at the end. For all other
+        // indices, both writes go to the same byte.
+        let mirror = (index.wrapping_sub(GROUP_WIDTH) & self.bucket_mask()) + GROUP_WIDTH;
+        self.ctrl[index] = ctrl;
+        self.ctrl[mirror] = ctrl;
+    }
+
+    /// The start positions of the groups to probe for `hash`, using triangular probing.
+    /// This visits every group exactly once, since the number of groups is a power of two.
+    fn probe_seq(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let mask = self.bucket_mask();
+        let mut pos = hash as usize & mask;
+        let mut stride = 0;
+        (0..self.buckets() / GROUP_WIDTH).map(move |_| {
+            let current = pos;
+            stride += GROUP_WIDTH;
+            pos = (pos + stride) & mask;
+            current
+        })
+    }
+
+    fn find(&self, hash: u64, mut eq: impl FnMut(&K) -> bool) -> Option<usize> {
+        if self.items == 0 {
+            return None;
+        }
+        let h2 = h2(hash);
+        for pos in self.probe_seq(hash) {
+            let group = Group::load(&self.ctrl[pos..]);
+            for bit in group.match_byte(h2) {
+                let index = (pos + bit) & self.bucket_mask();
+                // SAFETY: The control byte is full, so the slot is initialized.
+                let (key, _) = unsafe { self.slots[index].assume_init_ref() };
+                if eq(key) {
+                    return Some(index);
+                }
+            }
+            if group.match_empty().any() {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Find a slot that is `EMPTY` or `DELETED` to insert an element with `hash` into.
+    /// Returns the slot and its probe length.
+    fn find_insert_slot(&self, hash: u64) -> (usize, usize) {
+        for (i, pos) in self.probe_seq(hash).enumerate() {
+            let group = Group::load(&self.ctrl[pos..]);
+            if let Some(bit) = group.match_empty_or_deleted().lowest_set_bit() {
+                return ((pos + bit) & self.bucket_mask(), i * GROUP_WIDTH + bit);
+            }
+        }
+        unreachable!("the load factor guarantees free slots")
+    }
+
+    /// The probe length of the element with `hash` in slot `index`.
+    fn probe_len(&self, hash: u64, index: usize) -> usize {
+        // An element is always in the first group of its probe sequence that contains its
+        // slot, since that group had a free slot when the element was inserted.
+        for (i, pos) in self.probe_seq(hash).enumerate() {
+            let offset = index.wrapping_sub(pos) & self.bucket_mask();
+            if offset < GROUP_WIDTH {
+                return i * GROUP_WIDTH + offset;
+            }
+        }
+        unreachable!("the groups of a probe sequence cover every slot")
+    }
+
+    /// Insert an element into a free slot, without checking whether the key is already present.
+    /// There must be space left for the element in the table. Returns its probe length.
+    fn insert_in_free_slot(&mut self, hash: u64, entry: (K, V)) -> usize {
+        let (index, probe_len) = self.find_insert_slot(hash);
+        if self.ctrl[index] == EMPTY {
+            debug_assert_ne!(self.growth_left, 0);
+            self.growth_left -= 1;
+        }
+        self.set_ctrl(index, h2(hash));
+        self.slots[index].write(entry);
+        self.items += 1;
+        probe_len
+    }
+
+    /// Remove the element at the full slot `index`.
+    fn take(&mut self, index: usize) -> (K, V) {
+        debug_assert!(is_full(self.ctrl[index]));
+        self.set_ctrl(index, DELETED);
+        self.items -= 1;
+        // SAFETY: The slot was full, and we just marked it as deleted so it won't be read again.
+        unsafe { self.slots[index].assume_init_read() }
+    }
+
+    /// Remove all elements, returning them in slot order.
+    fn drain(&mut self) -> impl Iterator<Item = (K, V)> + '_ {
+        (0..self.buckets()).filter_map(|index| {
+            if is_full(self.ctrl[index]) {
+                Some(self.take(index))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl<K, V, A: Allocator> Drop for RawTable<K, V, A> {
+    fn drop(&mut self) {
+        if std::mem::needs_drop::<(K, V)>() {
+            self.drain().for_each(drop);
+        }
+    }
+}
+
+pub struct SwissHashMap<K, V, S = RandomState, A: Allocator = Global> {
+    table: RawTable<K, V, A>,
+    protection: HashDosProtection<S>,
+    s: S,
+}
+
+impl<K: Eq + Hash, V> SwissHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> SwissHashMap<K, V, S> {
+    pub fn with_hasher(state: S) -> Self {
+        HashMap::with_hasher_in(state, Global)
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> SwissHashMap<K, V, S, A> {
+    /// Switch to a new hash seed and rehash whenever an insertion has to probe much further
+    /// than it would with a good hash function.
+    ///
+    /// If the keys still collide with the new seed, the map stops reseeding.
+    pub fn enable_hashdos_protection(&mut self)
+    where
+        S: Reseed,
+    {
+        self.protection.enable();
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, A: Allocator + Clone> SwissHashMap<K, V, S, A> {
+    /// How well the keys are distributed over the table. `DELETED` slots count as occupied,
+    /// since probing has to go past them.
+    pub fn stats(&self) -> HashStats {
+        let table = &self.table;
+        let ctrl = &table.ctrl[..table.buckets()];
+        let probe_lens = ctrl
+            .iter()
+            .enumerate()
+            .filter(|&(_, &ctrl)| is_full(ctrl))
+            .map(|(index, _)| {
+                // SAFETY: The control byte is full, so the slot is initialized.
+                let (key, _) = unsafe { table.slots[index].assume_init_ref() };
+                table.probe_len(self.s.hash_one(key), index)
+            });
+        HashStats::new(ctrl.iter().map(|&ctrl| ctrl != EMPTY), probe_lens)
+    }
+
+    /// Make room for at least one more element, either by growing the table or,
+    /// if it is mostly full of `DELETED` slots, by rehashing it in place.
+    fn reserve_one(&mut self) {
+        let buckets = self.table.buckets();
+        let new = if buckets == 0 {
+            GROUP_WIDTH
+        } else if self.table.items < capacity_for_buckets(buckets) / 2 {
+            buckets
+        } else {
+            buckets * 2
+        };
+        handle_reserve(self.try_resize(new));
+    }
+
+    /// Move all elements into a new table with `new` buckets.
+    fn try_resize(&mut self, new: usize) -> Result<(), TryReserveError> {
+        let table = RawTable::try_with_buckets_in(new, self.table.allocator().clone())?;
+        self.rehash_into(table);
+        Ok(())
+    }
+
+    fn rehash_into(&mut self, table: RawTable<K, V, A>) {
+        let mut old = std::mem::replace(&mut self.table, table);
+        for (key, value) in old.drain() {
+            let hash = self.s.hash_one(&key);
+            self.table.insert_in_free_slot(hash, (key, value));
+        }
+    }
+
+    fn reseed_and_rehash(&mut self) {
+        // Allocate before reseeding, as the elements can't be found with the new seed until
+        // they are rehashed. Without the memory, the map just stays slow.
+        let alloc = self.table.allocator().clone();
+        let Ok(table) = RawTable::try_with_buckets_in(self.table.buckets(), alloc) else {
+            return;
+        };
+        self.protection.reseed(&mut self.s);
+        self.rehash_into(table);
+        let stats = self.stats();
+        self.protection.check_reseed(&stats);
+    }
+}
+
+impl<K, V, S, A: Allocator + Clone> HashMap<K, V, S, A> for SwissHashMap<K, V, S, A> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a;
+
+    type IterMut<'a>
+        = IterMut<'a, K, V>
+    where
+        Self: 'a;
+
+    fn with_hasher_in(state: S, alloc: A) -> Self {
+        Self {
+            table: RawTable::new_in(alloc),
+            protection: HashDosProtection::disabled(),
+            s: state,
+        }
+    }
+
+    fn with_capacity_and_hasher_in(capacity: usize, state: S, alloc: A) -> Self {
+        if capacity == 0 {
+            return Self::with_hasher_in(state, alloc);
+        }
+        let buckets = handle_reserve(buckets_for(capacity));
+        Self {
+            table: handle_reserve(RawTable::try_with_buckets_in(buckets, alloc)),
+            protection: HashDosProtection::disabled(),
+            s: state,
+        }
+    }
+
+    fn allocator(&self) -> &A {
+        self.table.allocator()
+    }
+
+    fn hasher(&self) -> &S {
+        &self.s
+    }
+
+    fn len(&self) -> usize {
+        self.table.items
+    }
+
+    fn get(&self, key: &K) -> Option<&V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let hash = self.s.hash_one(key);
+        let index = self.table.find(hash, |elem_key| elem_key == key)?;
+        // SAFETY: `find` only returns full slots.
+        let (_, value) = unsafe { self.table.slots[index].assume_init_ref() };
+        Some(value)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let hash = self.s.hash_one(key);
+        let index = self.table.find(hash, |elem_key| elem_key == key)?;
+        // SAFETY: `find` only returns full slots.
+        let (_, value) = unsafe { self.table.slots[index].assume_init_mut() };
+        Some(value)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let hash = self.s.hash_one(&key);
+        if let Some(index) = self.table.find(hash, |elem_key| *elem_key == key) {
+            // SAFETY: `find` only returns full slots.
+            let (_, old) = unsafe { self.table.slots[index].assume_init_mut() };
+            return Some(std::mem::replace(old, value));
+        }
+
+        // Filling a `DELETED` slot does not use up any more of the table, only `EMPTY` ones do.
+        let needs_space = self.table.buckets() == 0
+            || (self.table.growth_left == 0
+                && self.table.ctrl[self.table.find_insert_slot(hash).0] == EMPTY);
+        if needs_space {
+            self.reserve_one();
+        }
+        let probe_len = self.table.insert_in_free_slot(hash, (key, value));
+        if self
+            .protection
+            .is_triggered(probe_len, self.table.buckets())
+        {
+            self.reseed_and_rehash();
+        }
+        None
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let hash = self.s.hash_one(key);
+        let index = self.table.find(hash, |elem_key| elem_key == key)?;
+        let (_, value) = self.table.take(index);
+        Some(value)
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        if additional <= self.table.growth_left {
+            return Ok(());
+        }
+        let required = self
+            .table
+            .items
+            .checked_add(additional)
+            .ok_or_else(capacity_overflow)?;
+        // If the table is big enough, this only gets rid of the `DELETED` slots.
+        let new = buckets_for(required)?.max(self.table.buckets());
+        self.try_resize(new)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        Iter {
+            slots: self.table.slots.iter().zip(self.table.ctrl.iter()),
+            remaining: self.table.items,
+        }
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        IterMut {
+            slots: self.table.slots.iter_mut().zip(self.table.ctrl.iter()),
+            remaining: self.table.items,
+        }
+    }
+}
+
+impl_std_traits!(SwissHashMap);
+
+pub struct Iter<'a, K, V> {
+    slots: std::iter::Zip<std::slice::Iter<'a, MaybeUninit<(K, V)>>, std::slice::Iter<'a, u8>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (slot, _) = self.slots.find(|(_, &ctrl)| is_full(ctrl))?;
+        // SAFETY: The control byte is full, so the slot is initialized.
+        let (key, value) = unsafe { slot.assume_init_ref() };
+        let entry = (key, value);
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub struct IterMut<'a, K, V> {
+    slots: std::iter::Zip<std::slice::IterMut<'a, MaybeUninit<(K, V)>>, std::slice::Iter<'a, u8>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (slot, _) = self.slots.find(|(_, &ctrl)| is_full(ctrl))?;
+        // SAFETY: The control byte is full, so the slot is initialized.
+        let (key, value) = unsafe { slot.assume_init_mut() };
+        let entry = (&*key, value);
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    table: RawTable<K, V, A>,
+    next: usize,
+}
+
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.table.buckets() {
+            let index = self.next;
+            self.next += 1;
+            if is_full(self.table.ctrl[index]) {
+                return Some(self.table.take(index));
+            }
+        }
+        None
+    }
+}
+
+impl<K, V, S, A: Allocator> IntoIterator for SwissHashMap<K, V, S, A> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            table: self.table,
+            next: 0,
+        }
+    }
+}
+
+pub struct SwissHashMapFamily;
+impl HashMapFamily for SwissHashMapFamily {
+    type Map<K, V, S, A: Allocator + Clone> = SwissHashMap<K, V, S, A>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::{BitMask, Group, SwissHashMap, DELETED, EMPTY, GROUP_WIDTH};
+    use crate::hashmaps::{tests::WeakSeedState, HashMap};
+
+    #[test]
+    fn do_tests() {
+        crate::hashmaps::tests::run_tests::<super::SwissHashMapFamily>();
+    }
+
+    #[test]
+    fn group_matching() {
+        let mut ctrl = [EMPTY; GROUP_WIDTH];
+        ctrl[1] = 5;
+        ctrl[3] = DELETED;
+        ctrl[7] = 5;
+        ctrl[15] = 0;
+        let group = Group::load(&ctrl);
+
+        assert_eq!(group.match_byte(5).collect::<Vec<_>>(), [1, 7]);
+        assert_eq!(group.match_byte(0).collect::<Vec<_>>(), [15]);
+        assert!(!group.match_byte(6).any());
+        assert_eq!(group.match_empty().0, !0b1000_0000_1000_1010);
+        assert_eq!(group.match_empty_or_deleted().0, !0b1000_0000_1000_0010);
+        assert_eq!(BitMask(0).lowest_set_bit(), None);
+    }
+
+    #[test]
+    fn drops_elements() {
+        struct CountDrop(Rc<Cell<usize>>);
+        impl Drop for CountDrop {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Rc::new(Cell::new(0));
+        let mut m = SwissHashMap::new();
+        for i in 0..100 {
+            m.insert(i, CountDrop(drops.clone()));
+        }
+        drop(m.remove(&0));
+        assert_eq!(drops.get(), 1);
+
+        let mut iter = m.into_iter();
+        drop(iter.next());
+        drop(iter.next());
+        assert_eq!(drops.get(), 3);
+        drop(iter);
+        assert_eq!(drops.get(), 100);
+    }
+
+    #[test]
+    fn stats() {
+        let mut m = SwissHashMap::with_hasher(WeakSeedState::default());
+        for i in 0..40 {
+            m.insert(i, ());
+        }
+        // All keys have hash 0, so they fill the groups starting at 0, 16 and 48 in order.
+        let stats = m.stats();
+        assert_eq!(stats.len, 40);
+        assert_eq!(stats.probe_lengths, [1; 40]);
+        assert_eq!((stats.clusters, stats.max_cluster_len), (2, 32));
+        assert!(!stats.is_degenerate());
+    }
+
+    #[test]
+    fn hashdos_protection() {
+        let state = WeakSeedState {
+            seed: 0,
+            reseed_helps: true,
+        };
+        let mut m = SwissHashMap::with_hasher(state);
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.hasher().seed, 1);
+        assert!(!m.stats().is_degenerate(), "{}", m.stats());
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+
+        let mut m = SwissHashMap::with_hasher(WeakSeedState::default());
+        m.enable_hashdos_protection();
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        assert!(!m.protection.is_enabled());
+        assert!(m.stats().is_degenerate());
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+    }
+}
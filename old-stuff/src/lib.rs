@@ -1,5 +1,9 @@
+#![feature(allocator_api)]
+#![feature(container_error_extra)]
+#![feature(mapped_lock_guards)]
 #![feature(ptr_metadata)]
 #![feature(strict_provenance)]
+#![feature(try_reserve_kind)]
 
 pub mod cfg_match;
 pub mod hashmaps;
@@ -1,4 +1,10 @@
-use std::mem::{self, MaybeUninit};
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::Cell,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ptr::NonNull,
+};
 
 pub use pm::scratch_space;
 
@@ -17,6 +23,56 @@ impl<'a> Scratch<'a> {
     pub fn read<T: Default>(&mut self) -> T {
         T::default()
     }
+
+    /// Use the buffer as a bump allocator, e.g. for collections that should not touch the heap.
+    pub fn into_alloc(self) -> ScratchAlloc<'a> {
+        ScratchAlloc {
+            start: NonNull::from(&mut *self.0).cast(),
+            len: self.0.len(),
+            used: Cell::new(0),
+            _buf: PhantomData,
+        }
+    }
+}
+
+/// A bump allocator over a [`Scratch`] buffer. Freed memory is only reused once the allocator is
+/// dropped, so it's best for short-lived collections of a known size.
+pub struct ScratchAlloc<'a> {
+    start: NonNull<u8>,
+    len: usize,
+    used: Cell<usize>,
+    _buf: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl ScratchAlloc<'_> {
+    /// The number of bytes that were handed out, including padding.
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+}
+
+// SAFETY: The buffer is borrowed for `'a`, so it outlives every reference to the allocator, and
+// every block starts after the end of the previous one.
+unsafe impl Allocator for &ScratchAlloc<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let used = self.used.get();
+        let padding = self
+            .start
+            .as_ptr()
+            .wrapping_add(used)
+            .align_offset(layout.align());
+        let offset = used.checked_add(padding).ok_or(AllocError)?;
+        let end = offset.checked_add(layout.size()).ok_or(AllocError)?;
+        if end > self.len {
+            return Err(AllocError);
+        }
+        self.used.set(end);
+        // SAFETY: `offset <= end <= len`, so the pointer is within the buffer.
+        let ptr = unsafe { self.start.add(offset) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
 }
 
 #[macro_export]
@@ -68,6 +124,8 @@ mod tests {
     use pm::scratch_space;
 
     use super::Scratch;
+    use crate::hashmaps::{simple_open_addressing::SimpleOAHashMap, HashMap};
+    use std::hash::{BuildHasherDefault, DefaultHasher};
 
     #[scratch_space]
     fn has_scratch_space(mut scratch: Scratch<'_>) {
@@ -80,4 +138,18 @@ mod tests {
         define_scratch!(scratch, 100);
         has_scratch_space(scratch);
     }
+
+    #[test]
+    fn hash_map_in_scratch() {
+        define_scratch!(scratch, 1024);
+        let alloc = scratch.into_alloc();
+        let mut map =
+            SimpleOAHashMap::with_hasher_in(BuildHasherDefault::<DefaultHasher>::default(), &alloc);
+        for i in 0..10u32 {
+            assert_eq!(map.try_insert(i, i), Ok(None));
+        }
+        assert_ne!(alloc.used(), 0);
+        assert!(map.try_reserve(1000).is_err());
+        assert!((0..10).all(|i| map.get(&i) == Some(&i)));
+    }
 }
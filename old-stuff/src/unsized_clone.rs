@@ -1,16 +1,50 @@
-// TODO: This should probably be fallible instead of panic
-// TODO: Needs more safety docs around alignment
-
-use std::{marker::PhantomData, ptr::Pointee};
+use std::{
+    alloc::Layout,
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ptr::{self, Pointee},
+};
 
 /// a replacement for Clone (ignoring the old methods)
 pub trait NewClone {
+    /// Clone `self` into `place`, panicking if it doesn't fit
     fn clone_unsized<P>(&self, place: ClonePlace<P, Self>) -> InitClonePlace<P, Self>;
+
+    /// Clone `self` into `place`, or return how much space it needs if it doesn't fit,
+    /// so that the caller can try again with a bigger place
+    fn try_clone_unsized<P>(
+        &self,
+        place: ClonePlace<P, Self>,
+    ) -> Result<InitClonePlace<P, Self>, CloneError> {
+        place.check_fits(self)?;
+        Ok(self.clone_unsized(place))
+    }
 }
 
 /// a replacement for Copy
 pub trait NewCopy: NewClone {}
 
+/// Object safe cloning of sized values, which makes `dyn Trait` implement `NewClone`
+/// when it's a supertrait of `Trait`
+/// # Safety
+/// `clone_to_raw` has to fully initialize `size_of_val(self)` bytes at `ptr` with a valid
+/// value of the type of `self`, as `ClonePlace::clone_dyn` asserts them as initialized afterwards
+pub unsafe trait DynClone {
+    /// Clone `self` to `ptr`
+    /// # Safety
+    /// `ptr` has to be valid for writes of `size_of_val(self)` bytes and aligned to `align_of_val(self)`
+    unsafe fn clone_to_raw(&self, ptr: *mut u8);
+}
+
+// SAFETY: Writing a clone of `self` initializes exactly `size_of::<T>()` bytes
+unsafe impl<T: Clone> DynClone for T {
+    unsafe fn clone_to_raw(&self, ptr: *mut u8) {
+        ptr.cast::<T>().write(self.clone());
+    }
+}
+
 /// A trait which denotes a pointer to a place
 pub trait Pointer<T: ?Sized> {
     /// Create a pointer from a raw pointer
@@ -34,10 +68,46 @@ impl<T: ?Sized> Pointer<T> for &mut T {
 
 // more impls...
 
+/// The error returned when a value doesn't fit into a `ClonePlace`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloneError {
+    size: usize,
+    align: usize,
+}
+
+impl CloneError {
+    /// The size in bytes a place needs for the value
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The alignment a place needs for the value
+    pub fn align(&self) -> usize {
+        self.align
+    }
+}
+
+impl fmt::Display for CloneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "clone place doesn't fit, the value needs {} bytes aligned to {}",
+            self.size, self.align
+        )
+    }
+}
+
+impl Error for CloneError {}
+
 /// Denotes a place which something can be cloned into.
+/// `ptr` is valid for writes of `max_size` bytes and aligned to `align`.
 pub struct ClonePlace<P, T: ?Sized> {
     ptr: *mut u8,
     max_size: usize,
+    align: usize,
+    /// Whether `ptr` was allocated with the layout of `max_size` and `align`, and has to be
+    /// deallocated if nothing gets cloned into the place
+    owned: bool,
     _boo: PhantomData<(P, *const T)>,
 }
 
@@ -59,17 +129,43 @@ impl<P, T: ?Sized> ClonePlace<P, T> {
         self.max_size
     }
 
-    /// Create a new ClonePlace from a pointer and maximum size
+    /// Get the alignment of the place
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Create a new ClonePlace from a pointer, maximum size and alignment
     /// # Safety
-    /// `ptr` has to be valid for writes of size `max_size`
-    unsafe fn from_raw(ptr: *mut u8, max_size: usize) -> Self {
+    /// `ptr` has to be valid for writes of size `max_size` and aligned to `align`,
+    /// which has to be a power of two
+    unsafe fn from_raw(ptr: *mut u8, max_size: usize, align: usize) -> Self {
+        debug_assert!(align.is_power_of_two() && ptr.addr().is_multiple_of(align));
         Self {
             ptr,
             max_size,
+            align,
+            owned: false,
             _boo: PhantomData,
         }
     }
 
+    /// Check that `value` fits into the place, both its size and its alignment.
+    /// A place that owns its allocation only fits values of exactly its layout, as the
+    /// pointer will deallocate it with the layout of the value.
+    pub fn check_fits(&self, value: &T) -> Result<(), CloneError> {
+        let size = std::mem::size_of_val(value);
+        let align = std::mem::align_of_val(value);
+        let fits = if self.owned {
+            self.max_size == size && self.align == align
+        } else {
+            self.max_size >= size && self.align >= align
+        };
+        if !fits {
+            return Err(CloneError { size, align });
+        }
+        Ok(())
+    }
+
     /// Unsafely assert that the place has been initialized for as many bytes as covered
     /// by the metadata. This is done by using `as_ptr` and writing to it before
     /// # Safety
@@ -78,25 +174,66 @@ impl<P, T: ?Sized> ClonePlace<P, T> {
         self,
         metadata: <T as Pointee>::Metadata,
     ) -> InitClonePlace<P, T> {
+        let ptr = self.ptr;
+        // The allocation is owned by the initialized value now
+        mem::forget(self);
         InitClonePlace {
-            ptr: self.ptr,
+            ptr,
             metadata,
             _boo: PhantomData,
         }
     }
 }
 
+impl<P, T: ?Sized> Drop for ClonePlace<P, T> {
+    fn drop(&mut self) {
+        if self.owned {
+            // SAFETY: `owned` is only set when `ptr` was allocated with this layout
+            unsafe {
+                let layout = Layout::from_size_align_unchecked(self.max_size, self.align);
+                std::alloc::dealloc(self.ptr, layout);
+            }
+        }
+    }
+}
+
+impl<P, T: ?Sized + DynClone> ClonePlace<P, T> {
+    /// Clone a value through `DynClone`, which also works for trait objects
+    pub fn clone_dyn(self, value: &T) -> Result<InitClonePlace<P, T>, CloneError> {
+        self.check_fits(value)?;
+        // SAFETY: We just checked that `self.ptr` is big enough and aligned for `value`
+        //         which `clone_to_raw` initialized, so it's fine to assert it as init
+        //         If the clone panics, nothing has been written that would have to be dropped
+        unsafe {
+            value.clone_to_raw(self.ptr);
+            Ok(self.assert_init_with_meta(ptr::metadata(value)))
+        }
+    }
+}
+
 impl<P, T: ?Sized + NewCopy> ClonePlace<P, T> {
     /// Safe convenience function for implementing Clone via Copy
     pub fn copy_trivially(self, data: &T) -> InitClonePlace<P, T> {
+        match self.try_copy_trivially(data) {
+            Ok(init) => init,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Like `copy_trivially`, but returns an error if `data` doesn't fit
+    pub fn try_copy_trivially(self, data: &T) -> Result<InitClonePlace<P, T>, CloneError> {
+        self.check_fits(data)?;
         let size = std::mem::size_of_val(data);
-        assert!(self.max_size() >= size);
         // SAFETY: `data` is valid for reads of `sizeof(data)`
-        //         `self.ptr` must be writable for at least as many bytes as `self.max_size`, which we just asserted
+        //         `self.ptr` must be writable for at least as many bytes as `self.max_size`, which we just checked
+        //         `self.ptr` is aligned to `self.align`, which is at least `alignof(data)`, as we just checked
         //         We have initialized `self.ptr` by `sizeof(data)` bytes, meaning it's fine to assert it as init
         unsafe {
             std::ptr::copy_nonoverlapping(data as *const T as *const u8, self.ptr, size);
-            ClonePlace::assert_init_with_meta(self, std::ptr::metadata(data))
+            Ok(ClonePlace::assert_init_with_meta(
+                self,
+                std::ptr::metadata(data),
+            ))
         }
     }
 }
@@ -114,16 +251,46 @@ impl<P: Pointer<T>, T: ?Sized> InitClonePlace<P, T> {
 impl<T: ?Sized> ClonePlace<Box<T>, T> {
     /// Creates a new boxed ClonePlace and allocates as many bytes as required for `value`
     pub fn boxed(value: &T) -> Self {
+        let layout = Layout::for_value(value);
+        if layout.size() == 0 {
+            // SAFETY: A box of a zero sized value doesn't allocate, it only needs an aligned pointer
+            return unsafe {
+                Self::from_raw(
+                    std::ptr::without_provenance_mut(layout.align()),
+                    0,
+                    layout.align(),
+                )
+            };
+        }
         // SAFETY: We checked the pointer for null meaning it's valid for `laoyut.size()` bytes
+        //         and the allocator aligned it to `layout.align()`
         //         That's the safety requirement for creating a box basically so we're fine
         unsafe {
-            let layout = std::alloc::Layout::for_value(value);
             let allocated = std::alloc::alloc(layout);
             if allocated.is_null() {
                 std::alloc::handle_alloc_error(layout);
             }
-            Self::from_raw(allocated, layout.size())
+            let mut place = Self::from_raw(allocated, layout.size(), layout.align());
+            place.owned = true;
+            place
+        }
+    }
+}
+
+impl<'a, T: ?Sized> ClonePlace<&'a mut T, T> {
+    /// Creates a ClonePlace in the part of `buf` after the first address aligned to `align`,
+    /// which has to be a power of two. The place is empty if `buf` contains no such address.
+    pub fn aligned_within(buf: &'a mut [MaybeUninit<u8>], align: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        let offset = buf.as_ptr().align_offset(align).min(buf.len());
+        let buf = &mut buf[offset..];
+        if buf.is_empty() {
+            // SAFETY: A dangling aligned pointer is valid for writes of 0 bytes
+            return unsafe { Self::from_raw(std::ptr::without_provenance_mut(align), 0, align) };
         }
+        // SAFETY: `buf` is valid for writes of its length for `'a`, which the place borrows it
+        //         for, and its start is aligned to `align` as `offset` was less than its length
+        unsafe { Self::from_raw(buf.as_mut_ptr().cast(), buf.len(), align) }
     }
 }
 
@@ -131,10 +298,76 @@ impl NewClone for str {
     fn clone_unsized<P>(&self, place: ClonePlace<P, Self>) -> InitClonePlace<P, Self> {
         place.copy_trivially(self)
     }
+
+    fn try_clone_unsized<P>(
+        &self,
+        place: ClonePlace<P, Self>,
+    ) -> Result<InitClonePlace<P, Self>, CloneError> {
+        place.try_copy_trivially(self)
+    }
 }
 
 impl NewCopy for str {}
 
+impl<T: Clone> NewClone for [T] {
+    fn clone_unsized<P>(&self, place: ClonePlace<P, Self>) -> InitClonePlace<P, Self> {
+        match self.try_clone_unsized(place) {
+            Ok(init) => init,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    fn try_clone_unsized<P>(
+        &self,
+        place: ClonePlace<P, Self>,
+    ) -> Result<InitClonePlace<P, Self>, CloneError> {
+        /// Drops the elements cloned so far if a clone panics
+        struct Guard<T> {
+            ptr: *mut T,
+            len: usize,
+        }
+
+        impl<T> Drop for Guard<T> {
+            fn drop(&mut self) {
+                // SAFETY: The first `len` elements have been initialized and nothing else owns them
+                unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.len)) }
+            }
+        }
+
+        place.check_fits(self)?;
+        let mut guard = Guard {
+            ptr: place.ptr.cast::<T>(),
+            len: 0,
+        };
+        for item in self {
+            // SAFETY: The place is big enough and aligned for `self.len()` elements, as we checked
+            unsafe { guard.ptr.add(guard.len).write(item.clone()) };
+            guard.len += 1;
+        }
+        mem::forget(guard);
+        // SAFETY: We have initialized all `self.len()` elements
+        unsafe { Ok(place.assert_init_with_meta(self.len())) }
+    }
+}
+
+impl<T: Copy> NewCopy for [T] {}
+
+impl<T: ?Sized + DynClone> NewClone for T {
+    fn clone_unsized<P>(&self, place: ClonePlace<P, Self>) -> InitClonePlace<P, Self> {
+        match place.clone_dyn(self) {
+            Ok(init) => init,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    fn try_clone_unsized<P>(
+        &self,
+        place: ClonePlace<P, Self>,
+    ) -> Result<InitClonePlace<P, Self>, CloneError> {
+        place.clone_dyn(self)
+    }
+}
+
 #[test]
 fn boxit() {
     let str = "aaaa";
@@ -149,11 +382,168 @@ fn on_the_stack() {
     let mut storage = [std::mem::MaybeUninit::<u8>::uninit(); 10];
     let str = "aaaa";
 
-    // SAFETY: `storage` is valid for writes of 10 bytes.
+    // SAFETY: `storage` is valid for writes of 10 bytes, and everything is aligned to 1.
     let place: ClonePlace<&mut str, _> =
-        unsafe { ClonePlace::from_raw(storage.as_mut_ptr().cast::<u8>(), 10) };
+        unsafe { ClonePlace::from_raw(storage.as_mut_ptr().cast::<u8>(), 10, 1) };
 
     let init_place = str.clone_unsized(place);
     let the_box = init_place.into_init_value();
     assert_eq!(&*the_box, "aaaa");
 }
+
+#[test]
+fn on_the_stack_fallback() {
+    let mut small = [std::mem::MaybeUninit::<u8>::uninit(); 10];
+    let mut big = [std::mem::MaybeUninit::<u8>::uninit(); 32];
+    let str = "aaaaaaaaaaaaaaaa";
+
+    let place: ClonePlace<&mut str, _> = ClonePlace::aligned_within(&mut small, 1);
+    let Err(err) = str.try_clone_unsized(place) else {
+        panic!("16 bytes fit into 10");
+    };
+    assert_eq!((err.size(), err.align()), (16, 1));
+
+    let place: ClonePlace<&mut str, _> = ClonePlace::aligned_within(&mut big, err.align());
+    assert!(place.max_size() >= err.size());
+    let the_ref = str.try_clone_unsized(place).unwrap().into_init_value();
+    assert_eq!(&*the_ref, "aaaaaaaaaaaaaaaa");
+}
+
+#[test]
+fn alignment() {
+    let mut buf = [MaybeUninit::<u8>::uninit(); 32];
+    let value: &[u64] = &[1, 2];
+
+    let place = ClonePlace::<&mut [u64], [u64]>::aligned_within(&mut buf, 8);
+    assert!(place.as_ptr().addr().is_multiple_of(8));
+    assert!(place.max_size() >= 24);
+    assert_eq!(place.check_fits(value), Ok(()));
+    drop(place);
+
+    let place = ClonePlace::<&mut [u64], [u64]>::aligned_within(&mut buf, 1);
+    let err = place.check_fits(value).unwrap_err();
+    assert_eq!((err.size(), err.align()), (16, 8));
+    drop(place);
+
+    let place = ClonePlace::<&mut [u64], [u64]>::aligned_within(&mut buf[..3], 64);
+    assert!(place.max_size() <= 3);
+}
+
+#[test]
+fn slices() {
+    let slice: &[u32] = &[1, 2, 3];
+    let the_box = ClonePlace::boxed(slice)
+        .copy_trivially(slice)
+        .into_init_value();
+    assert_eq!(*the_box, [1, 2, 3]);
+
+    let slice = &[String::from("a"), String::from("b")][..];
+    let the_box = slice
+        .clone_unsized(ClonePlace::boxed(slice))
+        .into_init_value();
+    assert_eq!(*the_box, ["a", "b"]);
+
+    let empty: &[String] = &[];
+    let the_box = empty
+        .clone_unsized(ClonePlace::boxed(empty))
+        .into_init_value();
+    assert!(the_box.is_empty());
+}
+
+#[test]
+fn boxed_needs_exact_layout() {
+    let long: &[u32] = &[1, 2, 3];
+    let short: &[u32] = &[1, 2];
+    let err = short
+        .try_clone_unsized(ClonePlace::boxed(long))
+        .err()
+        .unwrap();
+    assert_eq!((err.size(), err.align()), (8, 4));
+
+    let same_len: &[u32] = &[4, 5, 6];
+    let the_box = same_len
+        .try_clone_unsized(ClonePlace::boxed(long))
+        .unwrap()
+        .into_init_value();
+    assert_eq!(*the_box, [4, 5, 6]);
+}
+
+#[test]
+fn slice_clone_panics() {
+    use std::{cell::Cell, panic, rc::Rc};
+
+    struct Bomb {
+        drops: Rc<Cell<usize>>,
+        explode: bool,
+    }
+
+    impl Clone for Bomb {
+        fn clone(&self) -> Self {
+            assert!(!self.explode, "boom");
+            Bomb {
+                drops: self.drops.clone(),
+                explode: false,
+            }
+        }
+    }
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    let drops = Rc::new(Cell::new(0));
+    let bomb = |explode| Bomb {
+        drops: drops.clone(),
+        explode,
+    };
+    let slice = &[bomb(false), bomb(false), bomb(true)][..];
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        slice.clone_unsized(ClonePlace::boxed(slice))
+    }));
+    assert!(result.is_err());
+    // Both clones were dropped once, and none of the originals
+    assert_eq!(drops.get(), 2);
+}
+
+#[test]
+fn trait_objects() {
+    trait Shape: DynClone + std::fmt::Debug {
+        fn area(&self) -> f64;
+    }
+
+    #[derive(Clone, Debug)]
+    struct Square(f64);
+
+    impl Shape for Square {
+        fn area(&self) -> f64 {
+            self.0 * self.0
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    #[allow(dead_code)]
+    struct Named(String, u64);
+
+    impl Shape for Named {
+        fn area(&self) -> f64 {
+            self.1 as f64
+        }
+    }
+
+    let shapes: [&dyn Shape; 2] = [&Square(2.0), &Named("uwu".to_owned(), 3)];
+    for shape in shapes {
+        let place: ClonePlace<Box<dyn Shape>, _> = ClonePlace::boxed(shape);
+        let the_box = shape.clone_unsized(place).into_init_value();
+        assert_eq!(the_box.area(), shape.area());
+        assert_eq!(format!("{the_box:?}"), format!("{shape:?}"));
+
+        let mut buf = [MaybeUninit::<u8>::uninit(); 64];
+        let place = ClonePlace::aligned_within(&mut buf, std::mem::align_of_val(shape));
+        let the_ref: &mut dyn Shape = shape.try_clone_unsized(place).unwrap().into_init_value();
+        assert_eq!(the_ref.area(), shape.area());
+        // SAFETY: The clone in `buf` is never used again
+        unsafe { ptr::drop_in_place(the_ref) };
+    }
+}